@@ -16,5 +16,7 @@
 extern crate alloc;
 #[cfg(all(feature = "alloc", feature = "rustc-std-workspace-alloc"))]
 extern crate rustc_std_workspace_alloc as alloc;
+#[cfg(any(feature = "std", test))]
+extern crate std;
 
 pub mod wasi_unstable;
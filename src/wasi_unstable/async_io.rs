@@ -0,0 +1,342 @@
+//! Asynchronous I/O on file descriptors, and timers.
+//!
+//! [`AsyncFile`] and [`AsyncSocket`] put a file descriptor in non-blocking
+//! mode with `FDFLAG_NONBLOCK` for as long as they wrap it. When an operation fails with `EAGAIN` they
+//! wait for the descriptor to become ready through the reactor in the
+//! `reactor` module, then retry. [`Sleep`] completes once
+//! `CLOCK_MONOTONIC` reaches a deadline.
+//!
+//! With the `futures-io` feature, `AsyncFile` and `AsyncSocket` implement
+//! `futures_io::AsyncRead` and `futures_io::AsyncWrite`.
+
+use super::reactor::Registration;
+use super::*;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+/// Retries `op` until it doesn't fail with `EAGAIN` or `EINTR`, waiting on
+/// `registration` while the descriptor isn't ready.
+fn poll_io<T>(
+    registration: &Registration,
+    cx: &mut Context,
+    mut op: impl FnMut() -> Result<T, Error>,
+) -> Poll<Result<T, Error>> {
+    loop {
+        match op() {
+            Err(EAGAIN) => match registration.poll_ready(cx) {
+                Poll::Ready(Ok(_)) => {}
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            },
+            Err(EINTR) => {}
+            result => return Poll::Ready(result),
+        }
+    }
+}
+
+/// Sets `FDFLAG_NONBLOCK` on `fd`, returning the flags to restore if it
+/// wasn't set.
+unsafe fn set_nonblocking(fd: Fd) -> Result<Option<FdFlags>, Error> {
+    let stat = fd_fdstat_get(fd)?;
+    if stat.fs_flags & FDFLAG_NONBLOCK != 0 {
+        return Ok(None);
+    }
+    fd_fdstat_set_flags(fd, stat.fs_flags | FDFLAG_NONBLOCK)?;
+    Ok(Some(stat.fs_flags))
+}
+
+/// Restores the flags saved by `set_nonblocking`.
+fn restore_flags(fd: Fd, flags: Option<FdFlags>) {
+    if let Some(flags) = flags {
+        // There is no way to report an error from `drop`, and the
+        // descriptor may well have been closed by now.
+        let _ = unsafe { fd_fdstat_set_flags(fd, flags) };
+    }
+}
+
+/// A file descriptor read and written asynchronously with `fd_read` and
+/// `fd_write`.
+///
+/// The descriptor isn't closed when the `AsyncFile` is dropped, but
+/// `FDFLAG_NONBLOCK` is cleared again if the `AsyncFile` set it, so that
+/// wrapping the standard streams doesn't leave them non-blocking for the
+/// rest of the program or for the processes sharing them.
+#[derive(Debug)]
+pub struct AsyncFile {
+    fd: Fd,
+    readable: Registration,
+    writable: Registration,
+    /// The flags to restore on drop.
+    flags: Option<FdFlags>,
+}
+
+impl AsyncFile {
+    /// Puts `fd` in non-blocking mode and wraps it.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must stay open for as long as the `AsyncFile` is used.
+    pub unsafe fn new(fd: Fd) -> Result<Self, Error> {
+        let flags = set_nonblocking(fd)?;
+        Ok(AsyncFile {
+            fd,
+            readable: Registration::fd_read(fd),
+            writable: Registration::fd_write(fd),
+            flags,
+        })
+    }
+
+    /// Wraps `STDIN_FD`.
+    pub fn stdin() -> Result<Self, Error> {
+        unsafe { AsyncFile::new(STDIN_FD) }
+    }
+
+    /// Wraps `STDOUT_FD`.
+    pub fn stdout() -> Result<Self, Error> {
+        unsafe { AsyncFile::new(STDOUT_FD) }
+    }
+
+    /// Wraps `STDERR_FD`.
+    pub fn stderr() -> Result<Self, Error> {
+        unsafe { AsyncFile::new(STDERR_FD) }
+    }
+
+    /// Returns the wrapped file descriptor.
+    pub fn fd(&self) -> Fd {
+        self.fd
+    }
+
+    /// Attempts to read into `buf`, returning `Pending` until `fd` is
+    /// readable.
+    pub fn poll_read(&self, cx: &mut Context, buf: &mut [u8]) -> Poll<Result<usize, Error>> {
+        let fd = self.fd;
+        poll_io(&self.readable, cx, || {
+            let iov = IoVec {
+                buf: buf.as_mut_ptr(),
+                buf_len: buf.len(),
+            };
+            unsafe { fd_read(fd, &[iov]) }
+        })
+    }
+
+    /// Attempts to write from `buf`, returning `Pending` until `fd` is
+    /// writable.
+    pub fn poll_write(&self, cx: &mut Context, buf: &[u8]) -> Poll<Result<usize, Error>> {
+        let fd = self.fd;
+        poll_io(&self.writable, cx, || {
+            let iov = CIoVec {
+                buf: buf.as_ptr(),
+                buf_len: buf.len(),
+            };
+            unsafe { fd_write(fd, &[iov]) }
+        })
+    }
+
+    /// Reads into `buf`, returning the number of bytes read.
+    pub async fn read(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        core::future::poll_fn(|cx| self.poll_read(cx, buf)).await
+    }
+
+    /// Writes from `buf`, returning the number of bytes written.
+    pub async fn write(&self, buf: &[u8]) -> Result<usize, Error> {
+        core::future::poll_fn(|cx| self.poll_write(cx, buf)).await
+    }
+
+    /// Writes all of `buf`.
+    pub async fn write_all(&self, mut buf: &[u8]) -> Result<(), Error> {
+        while !buf.is_empty() {
+            match self.write(buf).await? {
+                0 => return Err(EIO),
+                n => buf = buf.get(n..).ok_or(EIO)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AsyncFile {
+    fn drop(&mut self) {
+        restore_flags(self.fd, self.flags);
+    }
+}
+
+/// A socket received from the runtime, used asynchronously with
+/// `sock_recv` and `sock_send`.
+///
+/// The socket isn't closed when the `AsyncSocket` is dropped, but
+/// `FDFLAG_NONBLOCK` is cleared again if the `AsyncSocket` set it.
+#[derive(Debug)]
+pub struct AsyncSocket {
+    fd: Fd,
+    readable: Registration,
+    writable: Registration,
+    /// The flags to restore on drop.
+    flags: Option<FdFlags>,
+}
+
+impl AsyncSocket {
+    /// Puts `fd` in non-blocking mode and wraps it.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be a socket and stay open for as long as the
+    /// `AsyncSocket` is used.
+    pub unsafe fn new(fd: Fd) -> Result<Self, Error> {
+        let flags = set_nonblocking(fd)?;
+        Ok(AsyncSocket {
+            fd,
+            readable: Registration::fd_read(fd),
+            writable: Registration::fd_write(fd),
+            flags,
+        })
+    }
+
+    /// Returns the wrapped file descriptor.
+    pub fn fd(&self) -> Fd {
+        self.fd
+    }
+
+    /// Attempts to receive into `buf` with `flags`, returning `Pending`
+    /// until the socket is readable.
+    pub fn poll_recv(
+        &self,
+        cx: &mut Context,
+        buf: &mut [u8],
+        flags: RiFlags,
+    ) -> Poll<Result<(usize, RoFlags), Error>> {
+        let fd = self.fd;
+        poll_io(&self.readable, cx, || {
+            let iov = IoVec {
+                buf: buf.as_mut_ptr(),
+                buf_len: buf.len(),
+            };
+            unsafe { sock_recv(fd, &[iov], flags) }
+        })
+    }
+
+    /// Attempts to send from `buf`, returning `Pending` until the socket
+    /// is writable.
+    pub fn poll_send(&self, cx: &mut Context, buf: &[u8]) -> Poll<Result<usize, Error>> {
+        let fd = self.fd;
+        poll_io(&self.writable, cx, || {
+            let iov = CIoVec {
+                buf: buf.as_ptr(),
+                buf_len: buf.len(),
+            };
+            unsafe { sock_send(fd, &[iov], 0) }
+        })
+    }
+
+    /// Receives into `buf`, returning the number of bytes received and the
+    /// `RoFlags` reported by the host.
+    pub async fn recv(&self, buf: &mut [u8], flags: RiFlags) -> Result<(usize, RoFlags), Error> {
+        core::future::poll_fn(|cx| self.poll_recv(cx, buf, flags)).await
+    }
+
+    /// Sends from `buf`, returning the number of bytes sent.
+    pub async fn send(&self, buf: &[u8]) -> Result<usize, Error> {
+        core::future::poll_fn(|cx| self.poll_send(cx, buf)).await
+    }
+}
+
+impl Drop for AsyncSocket {
+    fn drop(&mut self) {
+        restore_flags(self.fd, self.flags);
+    }
+}
+
+/// A future completing once `CLOCK_MONOTONIC` reaches a deadline.
+#[derive(Debug)]
+pub struct Sleep {
+    deadline: Timestamp,
+    registration: Registration,
+}
+
+impl Sleep {
+    /// Creates a future completing at `deadline`, a `CLOCK_MONOTONIC`
+    /// timestamp.
+    pub fn until(deadline: Timestamp) -> Self {
+        Sleep {
+            deadline,
+            registration: Registration::clock(CLOCK_MONOTONIC, deadline),
+        }
+    }
+
+    /// Returns the deadline.
+    pub fn deadline(&self) -> Timestamp {
+        self.deadline
+    }
+}
+
+/// Returns a future completing after `nanos` nanoseconds.
+pub fn sleep(nanos: Timestamp) -> Result<Sleep, Error> {
+    let now = clock_time_get(CLOCK_MONOTONIC, 1)?;
+    Ok(Sleep::until(now.saturating_add(nanos)))
+}
+
+impl Future for Sleep {
+    /// The error the host reported for the clock subscription, if any.
+    type Output = Result<(), Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        self.registration.poll_ready(cx).map(|r| r.map(|_| ()))
+    }
+}
+
+#[cfg(feature = "futures-io")]
+mod futures_io_impls {
+    use super::*;
+    use futures_io::{AsyncRead, AsyncWrite};
+    use std::io;
+
+    fn to_io<T>(poll: Poll<Result<T, Error>>) -> Poll<io::Result<T>> {
+        poll.map(|r| r.map_err(io_error))
+    }
+
+    macro_rules! impl_async_io {
+        ($ty:ty, $read:ident, $write:ident) => {
+            impl AsyncRead for $ty {
+                fn poll_read(
+                    self: Pin<&mut Self>,
+                    cx: &mut Context,
+                    buf: &mut [u8],
+                ) -> Poll<io::Result<usize>> {
+                    to_io((*self).$read(cx, buf))
+                }
+            }
+
+            impl AsyncWrite for $ty {
+                fn poll_write(
+                    self: Pin<&mut Self>,
+                    cx: &mut Context,
+                    buf: &[u8],
+                ) -> Poll<io::Result<usize>> {
+                    to_io((*self).$write(cx, buf))
+                }
+
+                fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+                    Poll::Ready(Ok(()))
+                }
+
+                // The descriptor isn't owned, so there is nothing to close.
+                fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+                    Poll::Ready(Ok(()))
+                }
+            }
+        };
+    }
+
+    impl_async_io!(AsyncFile, poll_read, poll_write);
+    impl_async_io!(&AsyncFile, poll_read, poll_write);
+
+    impl AsyncSocket {
+        // Only the length is needed, the `RoFlags` are dropped.
+        fn poll_recv_len(&self, cx: &mut Context, buf: &mut [u8]) -> Poll<Result<usize, Error>> {
+            self.poll_recv(cx, buf, 0).map(|r| r.map(|(n, _)| n))
+        }
+    }
+
+    impl_async_io!(AsyncSocket, poll_recv_len, poll_send);
+    impl_async_io!(&AsyncSocket, poll_recv_len, poll_send);
+}
@@ -0,0 +1,225 @@
+//! A snapshot of the command-line arguments and environment variables.
+//!
+//! Reading them with `args_get` and `environ_get` takes four calls and a
+//! few allocations each time. [`Env::get`] does it once, on first use, and
+//! keeps the result for the rest of the process, so every library in the
+//! guest can look up a variable without going back to the host. Every
+//! string of an [`Env`] is kept in a single buffer.
+//!
+//! Since a host is free to pass arbitrarily large arguments and
+//! environments, [`set_size_limit`] caps how many bytes the snapshot may
+//! take before anything is allocated for it. The host writes the strings
+//! straight into the snapshot's buffer, so nothing larger is ever
+//! allocated.
+
+use super::*;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+static SNAPSHOT: AtomicPtr<Env> = AtomicPtr::new(core::ptr::null_mut());
+static SIZE_LIMIT: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Limits the size of the snapshot taken by [`Env::get`] to `limit` bytes
+/// of arguments and environment variables, counting their NUL terminators.
+///
+/// This has no effect once the snapshot has been taken.
+pub fn set_size_limit(limit: usize) {
+    SIZE_LIMIT.store(limit, Ordering::SeqCst);
+}
+
+/// The command-line arguments and environment variables of the process.
+pub struct Env {
+    /// The arguments followed by the variables, each terminated by a NUL,
+    /// as written by `args_get` and `environ_get`.
+    buf: Vec<u8>,
+    args_len: usize,
+    arg_count: usize,
+    var_count: usize,
+}
+
+impl Env {
+    /// Returns the snapshot of the process's arguments and environment,
+    /// reading it on the first call.
+    ///
+    /// Fails with `E2BIG` if they are larger than the limit set with
+    /// [`set_size_limit`]. A failure isn't cached, the next call tries
+    /// again.
+    pub fn get() -> Result<&'static Env, Error> {
+        let current = SNAPSHOT.load(Ordering::Acquire);
+        if !current.is_null() {
+            return Ok(unsafe { &*current });
+        }
+        let env = Env::read_with_limit(SIZE_LIMIT.load(Ordering::SeqCst))?;
+        let env = Box::into_raw(Box::new(env));
+        match SNAPSHOT.compare_exchange(
+            core::ptr::null_mut(),
+            env,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            // The snapshot is never freed, so it lives for the rest of the
+            // process.
+            Ok(_) => Ok(unsafe { &*env }),
+            Err(current) => {
+                drop(unsafe { Box::from_raw(env) });
+                Ok(unsafe { &*current })
+            }
+        }
+    }
+
+    /// Reads the process's arguments and environment into a new snapshot,
+    /// not shared with [`Env::get`].
+    pub fn read() -> Result<Env, Error> {
+        Env::read_with_limit(usize::MAX)
+    }
+
+    /// Reads the process's arguments and environment into a new snapshot,
+    /// failing with `E2BIG` before allocating anything if they take more
+    /// than `limit` bytes, counting their NUL terminators.
+    ///
+    /// Fails with `EIO` if the host reports more strings than fit in the
+    /// bytes it reports, or doesn't write them one after the other.
+    pub fn read_with_limit(limit: usize) -> Result<Env, Error> {
+        let args = args_sizes_get()?;
+        let vars = environ_sizes_get()?;
+        // Every string takes at least its NUL, so larger counts would only
+        // make us allocate more pointers.
+        if args.get_count() > args.get_buf_len() || vars.get_count() > vars.get_buf_len() {
+            return Err(EIO);
+        }
+        let len = args
+            .get_buf_len()
+            .checked_add(vars.get_buf_len())
+            .filter(|&len| len <= limit)
+            .ok_or(E2BIG)?;
+
+        let mut buf = vec![0; len];
+        let (args_buf, vars_buf) = buf.split_at_mut(args.get_buf_len());
+        // The pointers are only checked, both calls can share them.
+        let count = core::cmp::max(args.get_count(), vars.get_count());
+        let mut ptrs = vec![core::ptr::null_mut(); count];
+        unsafe { args_get_into(ptrs.as_mut_ptr(), args_buf.as_mut_ptr())? };
+        check_packed(&ptrs[..args.get_count()], args_buf)?;
+        unsafe { environ_get_into(ptrs.as_mut_ptr(), vars_buf.as_mut_ptr())? };
+        check_packed(&ptrs[..vars.get_count()], vars_buf)?;
+        Ok(Env {
+            buf,
+            args_len: args.get_buf_len(),
+            arg_count: args.get_count(),
+            var_count: vars.get_count(),
+        })
+    }
+
+    /// Returns the number of arguments, including the program name.
+    pub fn arg_count(&self) -> usize {
+        self.arg_count
+    }
+
+    /// Returns the arguments, starting with the program name.
+    pub fn args(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        self.buf[..self.args_len]
+            .split(|&c| c == 0)
+            .take(self.arg_count)
+    }
+
+    /// Returns the number of environment variables.
+    pub fn var_count(&self) -> usize {
+        self.var_count
+    }
+
+    /// Returns the environment variables as key-value pairs, split the same
+    /// way as by `environ_get`.
+    pub fn vars(&self) -> impl Iterator<Item = (&[u8], &[u8])> + '_ {
+        self.buf[self.args_len..]
+            .split(|&c| c == 0)
+            .take(self.var_count)
+            .map(split_env)
+    }
+
+    /// Returns the value of the first environment variable named `key`.
+    pub fn var(&self, key: &[u8]) -> Option<&[u8]> {
+        self.vars().find(|&(k, _)| k == key).map(|(_, val)| val)
+    }
+}
+
+/// Checks that the strings `ptrs` point to were written one after the other
+/// from the start of `buf`, each terminated by a NUL, the way [`Env`] reads
+/// them back. Fails with `EIO` otherwise.
+fn check_packed(ptrs: &[*mut u8], buf: &[u8]) -> Result<(), Error> {
+    let mut start = 0;
+    for &ptr in ptrs {
+        if !core::ptr::eq(ptr, buf[start..].as_ptr()) {
+            return Err(EIO);
+        }
+        let len = buf[start..].iter().position(|&c| c == 0).ok_or(EIO)?;
+        start += len + 1;
+    }
+    Ok(())
+}
+
+impl fmt::Debug for Env {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lossy = |s| String::from_utf8_lossy(s);
+        f.debug_struct("Env")
+            .field("args", &self.args().map(lossy).collect::<Vec<_>>())
+            .field(
+                "vars",
+                &self
+                    .vars()
+                    .map(|(key, val)| (lossy(key), lossy(val)))
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(args: &[u8], vars: &[u8]) -> Env {
+        let count = |s: &[u8]| s.iter().filter(|&&c| c == 0).count();
+        Env {
+            buf: [args, vars].concat(),
+            args_len: args.len(),
+            arg_count: count(args),
+            var_count: count(vars),
+        }
+    }
+
+    #[test]
+    fn vars_split_like_environ_get() {
+        let env = env(b"prog\0\0-v\0", b"A=1\0=foo\0=a=b\0==\0plain\0B=\0");
+        assert_eq!(env.args().collect::<Vec<_>>(), [&b"prog"[..], b"", b"-v"]);
+        assert_eq!(
+            env.vars().collect::<Vec<_>>(),
+            [
+                (&b"A"[..], &b"1"[..]),
+                (b"", b"=foo"),
+                (b"=a", b"b"),
+                (b"=", b""),
+                (b"", b"plain"),
+                (b"B", b""),
+            ]
+        );
+        assert_eq!(env.var(b"A"), Some(&b"1"[..]));
+        assert_eq!(env.var(b""), Some(&b"=foo"[..]));
+        assert_eq!(env.var(b"C"), None);
+    }
+
+    #[test]
+    fn packed_strings() {
+        let mut buf = *b"ab\0\0c\0";
+        let p = buf.as_mut_ptr();
+        let ptrs = unsafe { [p, p.add(3), p.add(4)] };
+        assert_eq!(check_packed(&ptrs, &buf), Ok(()));
+        assert_eq!(check_packed(&ptrs[..2], &buf), Ok(()));
+        let ptrs = unsafe { [p, p.add(4)] };
+        assert_eq!(check_packed(&ptrs, &buf), Err(EIO));
+        assert_eq!(check_packed(&[p], &buf[..2]), Err(EIO));
+    }
+}
@@ -0,0 +1,153 @@
+//! A minimal single-threaded executor.
+//!
+//! [`block_on`] runs a future to completion, along with any tasks started
+//! with [`spawn`]. Whenever no task can make progress, it turns the reactor
+//! in the `reactor` module, blocking in `poll_oneoff` until an event wakes
+//! one of them.
+
+use super::reactor::{self, Lock};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+type Task = Pin<Box<dyn Future<Output = ()>>>;
+
+/// The task id of the future passed to `block_on`.
+const MAIN: usize = usize::MAX;
+
+struct Tasks {
+    /// Spawned tasks by id; a slot is `None` while free or while its task
+    /// is being polled.
+    slots: Vec<Option<Task>>,
+    free: Vec<usize>,
+}
+
+// WASI programs are single-threaded, so tasks are polled on the thread that
+// spawned them, the same assumption `Lock` makes.
+unsafe impl Send for Tasks {}
+
+static TASKS: Lock<Tasks> = Lock::new(Tasks {
+    slots: Vec::new(),
+    free: Vec::new(),
+});
+
+/// Ids of the tasks that were woken and need to be polled.
+static READY: Lock<Vec<usize>> = Lock::new(Vec::new());
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+// A waker's data pointer is the id of its task, so wakers don't hold on to
+// tasks and waking one that has completed is harmless.
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone_waker, wake, wake, drop_waker);
+
+fn raw_waker(id: usize) -> RawWaker {
+    RawWaker::new(id as *const (), &VTABLE)
+}
+
+unsafe fn clone_waker(data: *const ()) -> RawWaker {
+    raw_waker(data as usize)
+}
+
+unsafe fn wake(data: *const ()) {
+    let id = data as usize;
+    let mut ready = READY.lock();
+    if !ready.contains(&id) {
+        ready.push(id);
+    }
+}
+
+unsafe fn drop_waker(_: *const ()) {}
+
+fn waker(id: usize) -> Waker {
+    unsafe { Waker::from_raw(raw_waker(id)) }
+}
+
+/// Starts running `future` as a task of the executor.
+///
+/// The task runs when `block_on` is called, until it completes. Tasks still
+/// running when `block_on` returns continue with the next call.
+///
+/// The future doesn't have to be `Send`, since WASI programs are
+/// single-threaded.
+pub fn spawn(future: impl Future<Output = ()> + 'static) {
+    let task: Task = Box::pin(future);
+    let id = {
+        let mut tasks = TASKS.lock();
+        match tasks.free.pop() {
+            Some(id) => {
+                tasks.slots[id] = Some(task);
+                id
+            }
+            None => {
+                tasks.slots.push(Some(task));
+                tasks.slots.len() - 1
+            }
+        }
+    };
+    waker(id).wake();
+}
+
+/// Runs `future` to completion, running spawned tasks alongside it.
+///
+/// # Panics
+///
+/// Panics if called from within `block_on`, or if `future` is waiting but
+/// neither a task nor the reactor can ever wake it.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        panic!("block_on called from within block_on");
+    }
+    struct Running;
+    impl Drop for Running {
+        fn drop(&mut self) {
+            RUNNING.store(false, Ordering::SeqCst);
+        }
+    }
+    let _running = Running;
+
+    let mut future = future;
+    // The future is shadowed, so it can't be moved again.
+    let mut future = unsafe { Pin::new_unchecked(&mut future) };
+    let main_waker = waker(MAIN);
+    main_waker.wake_by_ref();
+
+    loop {
+        let ready = core::mem::take(&mut *READY.lock());
+        for (i, &id) in ready.iter().enumerate() {
+            if id == MAIN {
+                let mut cx = Context::from_waker(&main_waker);
+                if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                    // Leave the other woken tasks for the next call.
+                    READY.lock().extend_from_slice(&ready[i + 1..]);
+                    return output;
+                }
+                continue;
+            }
+            // Take the task out so it can spawn tasks while it is polled.
+            let task = TASKS.lock().slots.get_mut(id).and_then(Option::take);
+            if let Some(mut task) = task {
+                let waker = waker(id);
+                let mut cx = Context::from_waker(&waker);
+                if task.as_mut().poll(&mut cx).is_pending() {
+                    TASKS.lock().slots[id] = Some(task);
+                } else {
+                    // Dropping the task may spawn or wake tasks.
+                    core::mem::drop(task);
+                    TASKS.lock().free.push(id);
+                }
+            }
+        }
+
+        // Pick up events without blocking while there are tasks to run, so
+        // busy tasks don't starve the ones waiting for events.
+        let idle = READY.lock().is_empty();
+        if idle && !reactor::is_waiting() {
+            panic!("block_on: the future can't make progress");
+        }
+        // Errors are reported to the waiting tasks by the reactor.
+        let _ = reactor::turn(idle);
+    }
+}
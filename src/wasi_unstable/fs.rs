@@ -0,0 +1,1346 @@
+//! Owned file and directory handles.
+//!
+//! [`File`] and [`Dir`] own a file descriptor and close it when dropped.
+//! Paths passed to `Dir` methods are resolved relative to the directory,
+//! which is how WASI grants access to the filesystem: a program typically
+//! starts from the pre-opened directories and opens everything else through
+//! them, with [`OpenOptions`].
+
+#[cfg(feature = "alloc")]
+use super::path::{Component, WasiPathBuf};
+use super::*;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "alloc")]
+use core::marker::PhantomData;
+use core::time::Duration;
+
+/// Information about a file, as returned by `fd_filestat_get` and
+/// `path_filestat_get`.
+#[derive(Copy, Clone)]
+pub struct Metadata {
+    stat: FileStat,
+}
+
+impl Metadata {
+    /// Returns the type of the file, one of the `FILETYPE_*` constants.
+    pub fn file_type(&self) -> FileType {
+        self.stat.st_filetype
+    }
+
+    /// Returns whether the file is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.file_type() == FILETYPE_DIRECTORY
+    }
+
+    /// Returns whether the file is a regular file.
+    pub fn is_file(&self) -> bool {
+        self.file_type() == FILETYPE_REGULAR_FILE
+    }
+
+    /// Returns whether the file is a symbolic link.
+    ///
+    /// Only metadata obtained without following symbolic links can
+    /// describe one, see [`Dir::symlink_metadata`].
+    pub fn is_symlink(&self) -> bool {
+        self.file_type() == FILETYPE_SYMBOLIC_LINK
+    }
+
+    /// Returns the size of the file in bytes.
+    pub fn len(&self) -> FileSize {
+        self.stat.st_size
+    }
+
+    /// Returns whether the file is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the file's inode.
+    pub fn ino(&self) -> Inode {
+        self.stat.st_ino
+    }
+
+    /// Returns the ID of the device containing the file.
+    pub fn dev(&self) -> Device {
+        self.stat.st_dev
+    }
+
+    /// Returns the number of hard links to the file.
+    pub fn nlink(&self) -> LinkCount {
+        self.stat.st_nlink
+    }
+
+    /// Returns the time of the last access, since the Unix epoch.
+    pub fn accessed(&self) -> Duration {
+        Duration::from_nanos(self.stat.st_atim)
+    }
+
+    /// Returns the time of the last modification, since the Unix epoch.
+    pub fn modified(&self) -> Duration {
+        Duration::from_nanos(self.stat.st_mtim)
+    }
+
+    /// Returns the time of the last status change, since the Unix epoch.
+    ///
+    /// WASI doesn't report creation times; this is `st_ctim`, which is the
+    /// closest it has.
+    pub fn created(&self) -> Duration {
+        Duration::from_nanos(self.stat.st_ctim)
+    }
+
+    /// Returns the raw `FileStat`.
+    pub fn as_raw(&self) -> &FileStat {
+        &self.stat
+    }
+}
+
+impl From<FileStat> for Metadata {
+    fn from(stat: FileStat) -> Self {
+        Metadata { stat }
+    }
+}
+
+/// Returns the name of a `FILETYPE_*` constant, without the prefix.
+fn file_type_name(file_type: FileType) -> Option<&'static str> {
+    let name = match file_type {
+        FILETYPE_UNKNOWN => "UNKNOWN",
+        FILETYPE_BLOCK_DEVICE => "BLOCK_DEVICE",
+        FILETYPE_CHARACTER_DEVICE => "CHARACTER_DEVICE",
+        FILETYPE_DIRECTORY => "DIRECTORY",
+        FILETYPE_REGULAR_FILE => "REGULAR_FILE",
+        FILETYPE_SOCKET_DGRAM => "SOCKET_DGRAM",
+        FILETYPE_SOCKET_STREAM => "SOCKET_STREAM",
+        FILETYPE_SYMBOLIC_LINK => "SYMBOLIC_LINK",
+        _ => return None,
+    };
+    Some(name)
+}
+
+impl fmt::Debug for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        struct FileTypeName(FileType);
+        impl fmt::Debug for FileTypeName {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match file_type_name(self.0) {
+                    Some(name) => f.write_str(name),
+                    None => write!(f, "{}", self.0),
+                }
+            }
+        }
+
+        f.debug_struct("Metadata")
+            .field("file_type", &FileTypeName(self.file_type()))
+            .field("len", &self.len())
+            .field("dev", &self.dev())
+            .field("ino", &self.ino())
+            .field("nlink", &self.nlink())
+            .field("accessed", &self.accessed())
+            .field("modified", &self.modified())
+            .field("created", &self.created())
+            .finish()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Time {
+    At(Timestamp),
+    Now,
+}
+
+/// Timestamps to set on a file with `set_times`.
+///
+/// Times that aren't set are left unchanged.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FileTimes {
+    accessed: Option<Time>,
+    modified: Option<Time>,
+}
+
+impl FileTimes {
+    /// Creates a `FileTimes` that leaves both times unchanged.
+    pub fn new() -> Self {
+        FileTimes::default()
+    }
+
+    /// Sets the time of the last access, in nanoseconds since the Unix
+    /// epoch.
+    pub fn set_accessed(self, time: Timestamp) -> Self {
+        FileTimes {
+            accessed: Some(Time::At(time)),
+            ..self
+        }
+    }
+
+    /// Sets the time of the last access to the current time.
+    pub fn set_accessed_now(self) -> Self {
+        FileTimes {
+            accessed: Some(Time::Now),
+            ..self
+        }
+    }
+
+    /// Sets the time of the last modification, in nanoseconds since the
+    /// Unix epoch.
+    pub fn set_modified(self, time: Timestamp) -> Self {
+        FileTimes {
+            modified: Some(Time::At(time)),
+            ..self
+        }
+    }
+
+    /// Sets the time of the last modification to the current time.
+    pub fn set_modified_now(self) -> Self {
+        FileTimes {
+            modified: Some(Time::Now),
+            ..self
+        }
+    }
+
+    /// Returns the `st_atim`, `st_mtim` and `fstflags` arguments of
+    /// `fd_filestat_set_times` and `path_filestat_set_times`.
+    pub fn to_raw(&self) -> (Timestamp, Timestamp, FstFlags) {
+        let mut flags = 0;
+        let atim = match self.accessed {
+            Some(Time::At(time)) => {
+                flags |= FILESTAT_SET_ATIM;
+                time
+            }
+            Some(Time::Now) => {
+                flags |= FILESTAT_SET_ATIM_NOW;
+                0
+            }
+            None => 0,
+        };
+        let mtim = match self.modified {
+            Some(Time::At(time)) => {
+                flags |= FILESTAT_SET_MTIM;
+                time
+            }
+            Some(Time::Now) => {
+                flags |= FILESTAT_SET_MTIM_NOW;
+                0
+            }
+            None => 0,
+        };
+        (atim, mtim, flags)
+    }
+}
+
+macro_rules! handle {
+    ($(#[$attr:meta])* $name:ident) => {
+        $(#[$attr])*
+        #[derive(Debug)]
+        pub struct $name {
+            fd: Fd,
+        }
+
+        impl $name {
+            /// Takes ownership of `fd`.
+            ///
+            /// # Safety
+            ///
+            /// `fd` must be open and nothing else may close it.
+            pub unsafe fn from_raw_fd(fd: Fd) -> Self {
+                $name { fd }
+            }
+
+            /// Returns the file descriptor.
+            pub fn as_raw_fd(&self) -> Fd {
+                self.fd
+            }
+
+            /// Returns the file descriptor, giving up ownership of it.
+            pub fn into_raw_fd(self) -> Fd {
+                let fd = self.fd;
+                core::mem::forget(self);
+                fd
+            }
+        }
+
+        impl Drop for $name {
+            fn drop(&mut self) {
+                let _ = unsafe { fd_close(self.fd) };
+            }
+        }
+    };
+}
+
+handle! {
+    /// An open file, closed when dropped.
+    File
+}
+
+handle! {
+    /// An open directory, closed when dropped.
+    Dir
+}
+
+impl File {
+    /// Returns the file's metadata.
+    pub fn metadata(&self) -> Result<Metadata, Error> {
+        unsafe { fd_filestat_get(self.fd) }.map(Metadata::from)
+    }
+
+    /// Sets the file's access and modification times.
+    pub fn set_times(&self, times: &FileTimes) -> Result<(), Error> {
+        let (atim, mtim, flags) = times.to_raw();
+        unsafe { fd_filestat_set_times(self.fd, atim, mtim, flags) }
+    }
+
+    /// Reads into `buf`, returning the number of bytes read.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let iov = IoVec {
+            buf: buf.as_mut_ptr(),
+            buf_len: buf.len(),
+        };
+        unsafe { fd_read(self.fd, &[iov]) }
+    }
+
+    /// Writes from `buf`, returning the number of bytes written.
+    pub fn write(&self, buf: &[u8]) -> Result<usize, Error> {
+        let iov = CIoVec {
+            buf: buf.as_ptr(),
+            buf_len: buf.len(),
+        };
+        unsafe { fd_write(self.fd, &[iov]) }
+    }
+
+    /// Writes all of `buf`, retrying on short writes.
+    pub fn write_all(&self, buf: &[u8]) -> Result<(), Error> {
+        stdio::write_all(self.fd, buf)
+    }
+
+    /// Waits for the file's data and metadata to reach storage.
+    pub fn sync_all(&self) -> Result<(), Error> {
+        unsafe { fd_sync(self.fd) }
+    }
+
+    /// Waits for the file's data to reach storage, along with the metadata
+    /// needed to read it back.
+    pub fn sync_data(&self) -> Result<(), Error> {
+        unsafe { fd_datasync(self.fd) }
+    }
+}
+
+impl Dir {
+    /// Returns the metadata of the directory itself.
+    pub fn dir_metadata(&self) -> Result<Metadata, Error> {
+        unsafe { fd_filestat_get(self.fd) }.map(Metadata::from)
+    }
+
+    /// Sets the access and modification times of the directory itself.
+    pub fn set_times(&self, times: &FileTimes) -> Result<(), Error> {
+        let (atim, mtim, flags) = times.to_raw();
+        unsafe { fd_filestat_set_times(self.fd, atim, mtim, flags) }
+    }
+
+    /// Sets the access and modification times of the file at `path`,
+    /// following symbolic links.
+    pub fn set_times_at(&self, path: impl AsRef<WasiPath>, times: &FileTimes) -> Result<(), Error> {
+        let (atim, mtim, flags) = times.to_raw();
+        unsafe { path_filestat_set_times(self.fd, LOOKUP_SYMLINK_FOLLOW, path, atim, mtim, flags) }
+    }
+
+    /// Returns the metadata of the file at `path`, following symbolic
+    /// links.
+    pub fn metadata(&self, path: impl AsRef<WasiPath>) -> Result<Metadata, Error> {
+        unsafe { path_filestat_get(self.fd, LOOKUP_SYMLINK_FOLLOW, path) }.map(Metadata::from)
+    }
+
+    /// Returns the metadata of the file at `path`, describing a symbolic
+    /// link itself rather than the file it points to.
+    pub fn symlink_metadata(&self, path: impl AsRef<WasiPath>) -> Result<Metadata, Error> {
+        unsafe { path_filestat_get(self.fd, 0, path) }.map(Metadata::from)
+    }
+
+    /// Reads the contents of the symbolic link at `path` into `buf`,
+    /// returning their length.
+    ///
+    /// Fails with `ERANGE` rather than returning truncated contents if they
+    /// fill `buf`, since `path_readlink` can't tell whether they fit
+    /// exactly.
+    pub fn read_link_into(
+        &self,
+        path: impl AsRef<WasiPath>,
+        buf: &mut [u8],
+    ) -> Result<usize, Error> {
+        let len = unsafe { path_readlink(self.fd, path, buf)? };
+        if len >= buf.len() {
+            return Err(ERANGE);
+        }
+        Ok(len)
+    }
+
+    /// Creates a directory at `path`.
+    pub fn create_dir(&self, path: impl AsRef<WasiPath>) -> Result<(), Error> {
+        unsafe { path_create_directory(self.fd, path) }
+    }
+
+    /// Creates a directory at `path` along with any missing parent
+    /// directories.
+    ///
+    /// Components that already exist as directories, or as symbolic links
+    /// to directories, are left alone.
+    pub fn create_dir_all(&self, path: impl AsRef<WasiPath>) -> Result<(), Error> {
+        let path = path.as_ref().as_bytes();
+        let ends = path
+            .iter()
+            .enumerate()
+            .filter(|&(i, &c)| c != b'/' && path.get(i + 1).is_none_or(|&n| n == b'/'))
+            .map(|(i, _)| i + 1);
+        for end in ends {
+            // A prefix of a valid path is valid too.
+            let prefix = WasiPath::from_bytes_unchecked(&path[..end]);
+            match self.create_dir(prefix) {
+                Err(EEXIST) if self.metadata(prefix).is_ok_and(|m| m.is_dir()) => {}
+                result => result?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the empty directory at `path`.
+    pub fn remove_dir(&self, path: impl AsRef<WasiPath>) -> Result<(), Error> {
+        unsafe { path_remove_directory(self.fd, path) }
+    }
+
+    /// Removes the file or symbolic link at `path`.
+    pub fn remove_file(&self, path: impl AsRef<WasiPath>) -> Result<(), Error> {
+        unsafe { path_unlink_file(self.fd, path) }
+    }
+}
+
+/// An entry of a directory, as returned by `fd_readdir`.
+#[cfg(feature = "alloc")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirEntry {
+    name: Vec<u8>,
+    ino: Inode,
+    file_type: FileType,
+    next: DirCookie,
+}
+
+#[cfg(feature = "alloc")]
+impl DirEntry {
+    /// Returns the entry's name.
+    pub fn file_name(&self) -> &[u8] {
+        &self.name
+    }
+
+    /// Returns the entry's name, consuming the entry.
+    pub fn into_file_name(self) -> Vec<u8> {
+        self.name
+    }
+
+    /// Returns the inode of the file the entry refers to.
+    pub fn ino(&self) -> Inode {
+        self.ino
+    }
+
+    /// Returns the type of the file, one of the `FILETYPE_*` constants.
+    ///
+    /// A symbolic link is reported as such, not as the type of its target.
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    /// Returns whether the entry is `.` or `..`.
+    pub fn is_dot(&self) -> bool {
+        self.name == b"." || self.name == b".."
+    }
+
+    /// Returns the cookie to pass to `fd_readdir` to read the entries after
+    /// this one.
+    pub fn next_cookie(&self) -> DirCookie {
+        self.next
+    }
+}
+
+/// Reads the entries of a directory with `fd_readdir`, a buffer at a time.
+#[cfg(feature = "alloc")]
+#[derive(Debug)]
+pub(crate) struct DirReader {
+    fd: Fd,
+    buf: Vec<u8>,
+    /// The entries in `buf[pos..len]` haven't been returned yet.
+    pos: usize,
+    len: usize,
+    cookie: DirCookie,
+    /// The cookie `buf` was last read from.
+    read_cookie: DirCookie,
+    /// Whether `buf` holds the last entries of the directory.
+    eof: bool,
+}
+
+#[cfg(feature = "alloc")]
+impl DirReader {
+    pub(crate) fn new(fd: Fd) -> Self {
+        DirReader {
+            fd,
+            buf: alloc::vec![0; 4096],
+            pos: 0,
+            len: 0,
+            cookie: DIRCOOKIE_START,
+            read_cookie: DIRCOOKIE_START,
+            eof: false,
+        }
+    }
+
+    /// Parses the entry at `pos`, if `buf` holds all of it.
+    fn parse(&self) -> Option<(DirEntry, usize)> {
+        let header = core::mem::size_of::<Dirent>();
+        let buf = &self.buf[self.pos..self.len];
+        if buf.len() < header {
+            return None;
+        }
+        let dirent = unsafe { core::ptr::read_unaligned(buf.as_ptr() as *const Dirent) };
+        let name = buf.get(header..header.checked_add(dirent.d_namlen as usize)?)?;
+        let entry = DirEntry {
+            name: name.to_vec(),
+            ino: dirent.d_ino,
+            file_type: dirent.d_type,
+            next: dirent.d_next,
+        };
+        Some((entry, header + name.len()))
+    }
+
+    /// Returns the next entry, reading more of the directory when needed.
+    ///
+    /// Once an error has been returned, the reader is done. Fails with
+    /// `EIO` if the host's cookies don't advance, which would otherwise
+    /// return the same entries forever.
+    pub(crate) fn next_entry(&mut self) -> Option<Result<DirEntry, Error>> {
+        loop {
+            if let Some((entry, len)) = self.parse() {
+                self.pos += len;
+                self.cookie = entry.next;
+                return Some(Ok(entry));
+            }
+            if self.eof {
+                return None;
+            }
+            if self.pos == 0 && self.len == self.buf.len() {
+                // A single entry doesn't fit in the buffer.
+                let len = self.buf.len() * 2;
+                self.buf.resize(len, 0);
+            }
+            let stalled = self.cookie == self.read_cookie || self.cookie == DIRCOOKIE_START;
+            if self.pos > 0 && stalled {
+                // Entries were returned, yet reading on would start over.
+                return Some(Err(self.fail(EIO)));
+            }
+            self.read_cookie = self.cookie;
+            match unsafe { fd_readdir(self.fd, &mut self.buf, self.cookie) } {
+                Ok(len) => {
+                    self.pos = 0;
+                    self.len = len;
+                    self.eof = len < self.buf.len();
+                }
+                Err(err) => return Some(Err(self.fail(err))),
+            }
+        }
+    }
+
+    /// Stops reading, so `err` is the last thing returned.
+    fn fail(&mut self, err: Error) -> Error {
+        self.pos = 0;
+        self.len = 0;
+        self.eof = true;
+        err
+    }
+}
+
+/// An iterator over the entries of a directory, returned by
+/// [`Dir::read_dir`].
+#[cfg(feature = "alloc")]
+#[derive(Debug)]
+pub struct ReadDir<'a> {
+    reader: DirReader,
+    marker: PhantomData<&'a Dir>,
+}
+
+#[cfg(feature = "alloc")]
+impl Iterator for ReadDir<'_> {
+    type Item = Result<DirEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.reader.next_entry()
+    }
+}
+
+/// Returns the entries of `dir`, without `.` and `..`, sorted by name if
+/// `sort` is set and in the order `fd_readdir` reports them otherwise.
+///
+/// Fails with `EIO` if the host's `d_next` cookies don't advance, rather
+/// than reading the same entries forever.
+#[cfg(feature = "alloc")]
+pub fn read_dir_to_vec(dir: &Dir, sort: bool) -> Result<Vec<DirEntry>, Error> {
+    let mut entries = Vec::new();
+    for entry in dir.read_dir() {
+        let entry = entry?;
+        if !entry.is_dot() {
+            entries.push(entry);
+        }
+    }
+    if sort {
+        entries.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+    }
+    Ok(entries)
+}
+
+#[cfg(feature = "alloc")]
+impl Dir {
+    /// Returns an iterator over the directory's entries, including `.` and
+    /// `..`.
+    ///
+    /// The iterator stops after returning an error.
+    pub fn read_dir(&self) -> ReadDir<'_> {
+        ReadDir {
+            reader: DirReader::new(self.fd),
+            marker: PhantomData,
+        }
+    }
+
+    /// Removes the directory at `path` along with everything in it.
+    ///
+    /// Symbolic links are removed, not followed, and if `path` itself is a
+    /// symbolic link only the link is removed. Subdirectories are opened
+    /// without following symbolic links and emptied through their own
+    /// descriptors, so replacing one with a link while this runs can't make
+    /// it remove anything outside the tree.
+    pub fn remove_dir_all(&self, path: impl AsRef<WasiPath>) -> Result<(), Error> {
+        let path = path.as_ref();
+        if self.symlink_metadata(path)?.is_symlink() {
+            return self.remove_file(path);
+        }
+        self.open_subdir(path)?.remove_contents()?;
+        self.remove_dir(path)
+    }
+
+    /// Replaces the contents of the file at `path` with `data`, so that
+    /// after a crash the file holds either the old or the new contents.
+    ///
+    /// The data is written to a new, uniquely named file next to `path`,
+    /// synced to storage and renamed over `path`. The temporary file is
+    /// removed if anything fails before the rename.
+    pub fn write_atomic(&self, path: impl AsRef<WasiPath>, data: &[u8]) -> Result<(), Error> {
+        let path = path.as_ref();
+        let (temp_path, file) = self.create_temp_beside(path)?;
+        let result = file
+            .write_all(data)
+            .and_then(|()| file.sync_all())
+            .and_then(|()| {
+                drop(file);
+                unsafe { path_rename(self.fd, &temp_path, self.fd, path) }
+            });
+        if result.is_err() {
+            let _ = self.remove_file(&temp_path);
+        }
+        result
+    }
+
+    /// Creates a new file in the same directory as `path`, named after it
+    /// with a random suffix.
+    fn create_temp_beside(&self, path: &WasiPath) -> Result<(WasiPathBuf, File), Error> {
+        let path = path.as_bytes();
+        let name_start = path.iter().rposition(|&c| c == b'/').map_or(0, |i| i + 1);
+        let (parent, name) = path.split_at(name_start);
+        if name.is_empty() {
+            return Err(EISDIR);
+        }
+        let mut prefix = Vec::with_capacity(path.len() + 2);
+        prefix.extend_from_slice(parent);
+        prefix.push(b'.');
+        prefix.extend_from_slice(name);
+        prefix.push(b'.');
+        create_unique(&prefix, b".tmp", |temp_path| {
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(self, temp_path)
+        })
+    }
+
+    /// Returns the contents of the symbolic link at `path`.
+    ///
+    /// The buffer is doubled until the contents fit in it with room to
+    /// spare, which is how a complete result is told apart from a truncated
+    /// one. Fails with `ENAMETOOLONG` if they don't fit in 1 MiB.
+    pub fn read_link(&self, path: impl AsRef<WasiPath>) -> Result<Vec<u8>, Error> {
+        const MAX_LEN: usize = 1 << 20;
+        let path = path.as_ref();
+        let mut buf = alloc::vec![0; 256];
+        loop {
+            match self.read_link_into(path, &mut buf) {
+                Ok(len) => {
+                    buf.truncate(len);
+                    return Ok(buf);
+                }
+                Err(ERANGE) if buf.len() < MAX_LEN => {
+                    let len = buf.len() * 2;
+                    buf.resize(len, 0);
+                }
+                Err(ERANGE) => return Err(ENAMETOOLONG),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Returns `path` relative to the directory with every symbolic link,
+    /// `.` and `..` resolved, or `.` for the directory itself.
+    ///
+    /// Each component is examined with `path_filestat_get` and symbolic
+    /// links are read with `path_readlink`. Resolution fails with `ELOOP`
+    /// after following 40 links, and with `ENOTCAPABLE` if `path` or a
+    /// link leads outside the directory, through `..` or an absolute path.
+    pub fn canonicalize(&self, path: impl AsRef<WasiPath>) -> Result<WasiPathBuf, Error> {
+        const MAX_LINKS: usize = 40;
+
+        fn push_components(pending: &mut Vec<Vec<u8>>, path: &WasiPath) {
+            // Reversed, so the first component is popped first.
+            for component in path.components().rev() {
+                match component {
+                    Component::CurDir => {}
+                    Component::ParentDir => pending.push(b"..".to_vec()),
+                    Component::Normal(name) => pending.push(name.to_vec()),
+                }
+            }
+        }
+
+        let mut pending = Vec::new();
+        push_components(&mut pending, path.as_ref());
+        let mut resolved = WasiPathBuf::new();
+        let mut links = 0;
+        while let Some(component) = pending.pop() {
+            if component == b".." {
+                if !resolved.pop() {
+                    return Err(ENOTCAPABLE);
+                }
+                continue;
+            }
+            resolved.push(WasiPath::from_bytes_unchecked(&component));
+            if self.symlink_metadata(&resolved)?.is_symlink() {
+                links += 1;
+                if links > MAX_LINKS {
+                    return Err(ELOOP);
+                }
+                let target = self.read_link(&resolved)?;
+                resolved.pop();
+                push_components(&mut pending, WasiPath::new(&target)?);
+            }
+        }
+        if resolved.is_empty() {
+            resolved.push(WasiPath::from_bytes_unchecked(b"."));
+        }
+        Ok(resolved)
+    }
+
+    /// Opens the directory `name` without following symbolic links.
+    fn open_subdir(&self, name: &WasiPath) -> Result<Dir, Error> {
+        OpenOptions::new()
+            .follow_symlinks(false)
+            .open_dir(self, name)
+    }
+
+    /// Removes everything in the directory.
+    fn remove_contents(&self) -> Result<(), Error> {
+        // Removing entries while reading the directory can make it skip
+        // entries, so read a batch of names first and start over until
+        // the directory is empty.
+        loop {
+            let mut entries = Vec::new();
+            for entry in self.read_dir() {
+                let entry = entry?;
+                if !entry.is_dot() {
+                    entries.push(entry);
+                }
+                if entries.len() == 256 {
+                    break;
+                }
+            }
+            if entries.is_empty() {
+                return Ok(());
+            }
+            for entry in entries {
+                let name = WasiPath::from_bytes_unchecked(entry.file_name());
+                let mut file_type = entry.file_type();
+                if file_type == FILETYPE_UNKNOWN {
+                    file_type = self.symlink_metadata(name)?.file_type();
+                }
+                if file_type == FILETYPE_DIRECTORY {
+                    self.open_subdir(name)?.remove_contents()?;
+                    self.remove_dir(name)?;
+                } else {
+                    self.remove_file(name)?;
+                }
+            }
+        }
+    }
+}
+
+/// Calls `create` with a name made of `prefix`, 16 random hex digits and
+/// `suffix`, trying new names while it fails with `EEXIST`.
+///
+/// Returns the name that worked along with what `create` returned.
+#[cfg(feature = "alloc")]
+pub(crate) fn create_unique<T>(
+    prefix: &[u8],
+    suffix: &[u8],
+    mut create: impl FnMut(&WasiPath) -> Result<T, Error>,
+) -> Result<(WasiPathBuf, T), Error> {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    // Retry a few times in the unlikely case the name is taken.
+    for _ in 0..8 {
+        let mut random = [0; 8];
+        random_get(&mut random)?;
+        let mut name = Vec::with_capacity(prefix.len() + 16 + suffix.len());
+        name.extend_from_slice(prefix);
+        for byte in random.iter() {
+            name.push(HEX[usize::from(byte >> 4)]);
+            name.push(HEX[usize::from(byte & 0xf)]);
+        }
+        name.extend_from_slice(suffix);
+        // Built from a valid prefix, hex digits and a valid suffix.
+        let name = WasiPathBuf::from_bytes_unchecked(name);
+        match create(&name) {
+            Ok(value) => return Ok((name, value)),
+            Err(EEXIST) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Err(EEXIST)
+}
+
+/// The rights needed to read a file.
+const READ_RIGHTS: Rights = RIGHT_FD_READ
+    | RIGHT_FD_SEEK
+    | RIGHT_FD_TELL
+    | RIGHT_FD_ADVISE
+    | RIGHT_FD_FILESTAT_GET
+    | RIGHT_POLL_FD_READWRITE;
+
+/// The rights needed to write to a file.
+const WRITE_RIGHTS: Rights = RIGHT_FD_WRITE
+    | RIGHT_FD_SEEK
+    | RIGHT_FD_TELL
+    | RIGHT_FD_DATASYNC
+    | RIGHT_FD_SYNC
+    | RIGHT_FD_ALLOCATE
+    | RIGHT_FD_FILESTAT_GET
+    | RIGHT_FD_FILESTAT_SET_SIZE
+    | RIGHT_FD_FILESTAT_SET_TIMES
+    | RIGHT_POLL_FD_READWRITE;
+
+/// The rights needed to look up, list and modify the contents of a
+/// directory.
+const DIR_RIGHTS: Rights = RIGHT_FD_READDIR
+    | RIGHT_FD_FILESTAT_GET
+    | RIGHT_FD_FILESTAT_SET_TIMES
+    | RIGHT_PATH_CREATE_DIRECTORY
+    | RIGHT_PATH_CREATE_FILE
+    | RIGHT_PATH_LINK_SOURCE
+    | RIGHT_PATH_LINK_TARGET
+    | RIGHT_PATH_OPEN
+    | RIGHT_PATH_READLINK
+    | RIGHT_PATH_RENAME_SOURCE
+    | RIGHT_PATH_RENAME_TARGET
+    | RIGHT_PATH_FILESTAT_GET
+    | RIGHT_PATH_FILESTAT_SET_SIZE
+    | RIGHT_PATH_FILESTAT_SET_TIMES
+    | RIGHT_PATH_SYMLINK
+    | RIGHT_PATH_REMOVE_DIRECTORY
+    | RIGHT_PATH_UNLINK_FILE;
+
+/// Options for opening a file relative to a directory, like
+/// `std::fs::OpenOptions`.
+///
+/// The options are turned into the flags and rights `path_open` takes.
+/// Only the rights needed for the requested operations are asked for; the
+/// runtime further limits them to what the directory may pass on.
+#[derive(Copy, Clone, Debug)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    append: bool,
+    truncate: bool,
+    create: bool,
+    create_new: bool,
+    directory: bool,
+    follow_symlinks: bool,
+    nonblocking: bool,
+    sync: bool,
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        OpenOptions::new()
+    }
+}
+
+impl OpenOptions {
+    /// Creates options with every mode off, except following symbolic
+    /// links.
+    pub const fn new() -> Self {
+        OpenOptions {
+            read: false,
+            write: false,
+            append: false,
+            truncate: false,
+            create: false,
+            create_new: false,
+            directory: false,
+            follow_symlinks: true,
+            nonblocking: false,
+            sync: false,
+        }
+    }
+
+    /// Opens the file for reading.
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    /// Opens the file for writing.
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    /// Opens the file for writing, with every write going to the end of
+    /// the file, using `FDFLAG_APPEND`.
+    pub fn append(&mut self, append: bool) -> &mut Self {
+        self.append = append;
+        self
+    }
+
+    /// Truncates the file to zero length, using `O_TRUNC`. Requires `write`.
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+
+    /// Creates the file if it doesn't exist, using `O_CREAT`. Requires
+    /// `write` or `append`.
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    /// Creates the file, failing with `EEXIST` if it already exists, using
+    /// `O_CREAT | O_EXCL`. Requires `write` or `append`, and overrides
+    /// `create` and `truncate`.
+    pub fn create_new(&mut self, create_new: bool) -> &mut Self {
+        self.create_new = create_new;
+        self
+    }
+
+    /// Requires the file to be a directory, using `O_DIRECTORY`, and asks
+    /// for the rights to use it as one.
+    pub fn directory(&mut self, directory: bool) -> &mut Self {
+        self.directory = directory;
+        self
+    }
+
+    /// Follows a symbolic link in the last component of the path, using
+    /// `LOOKUP_SYMLINK_FOLLOW`. On by default.
+    pub fn follow_symlinks(&mut self, follow_symlinks: bool) -> &mut Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// Opens the file in non-blocking mode, using `FDFLAG_NONBLOCK`.
+    pub fn nonblocking(&mut self, nonblocking: bool) -> &mut Self {
+        self.nonblocking = nonblocking;
+        self
+    }
+
+    /// Makes every write wait for the data and metadata to reach storage,
+    /// using `FDFLAG_SYNC`.
+    pub fn sync(&mut self, sync: bool) -> &mut Self {
+        self.sync = sync;
+        self
+    }
+
+    /// Returns the `dirflags` argument of `path_open`.
+    pub fn lookup_flags(&self) -> LookupFlags {
+        if self.follow_symlinks && !self.create_new {
+            LOOKUP_SYMLINK_FOLLOW
+        } else {
+            0
+        }
+    }
+
+    /// Returns the `oflags` argument of `path_open`.
+    pub fn oflags(&self) -> OFlags {
+        let mut oflags = 0;
+        if self.create_new {
+            oflags |= O_CREAT | O_EXCL;
+        } else {
+            if self.create {
+                oflags |= O_CREAT;
+            }
+            if self.truncate {
+                oflags |= O_TRUNC;
+            }
+        }
+        if self.directory {
+            oflags |= O_DIRECTORY;
+        }
+        oflags
+    }
+
+    /// Returns the `fs_flags` argument of `path_open`.
+    pub fn fd_flags(&self) -> FdFlags {
+        let mut flags = 0;
+        if self.append {
+            flags |= FDFLAG_APPEND;
+        }
+        if self.nonblocking {
+            flags |= FDFLAG_NONBLOCK;
+        }
+        if self.sync {
+            flags |= FDFLAG_SYNC;
+        }
+        flags
+    }
+
+    /// Returns the `fs_rights_base` and `fs_rights_inheriting` arguments of
+    /// `path_open`.
+    ///
+    /// A directory is given every right as inheriting rights, so that files
+    /// opened through it can ask for whatever they need.
+    pub fn rights(&self) -> (Rights, Rights) {
+        let mut base = 0;
+        if self.read {
+            base |= READ_RIGHTS;
+        }
+        if self.write || self.append {
+            base |= WRITE_RIGHTS;
+        }
+        if self.sync {
+            base |= RIGHT_FD_SYNC;
+        }
+        if self.directory {
+            (base | DIR_RIGHTS, DIR_RIGHTS | READ_RIGHTS | WRITE_RIGHTS)
+        } else {
+            (base, 0)
+        }
+    }
+
+    /// Checks that the options make sense together, the way
+    /// `std::fs::OpenOptions` does.
+    fn check(&self) -> Result<(), Error> {
+        let write = self.write || self.append;
+        if !self.read && !write && !self.directory {
+            return Err(EINVAL);
+        }
+        if (self.truncate || self.create || self.create_new) && !write {
+            return Err(EINVAL);
+        }
+        if self.truncate && self.append && !self.create_new {
+            return Err(EINVAL);
+        }
+        Ok(())
+    }
+
+    /// Opens `path` relative to `dirfd`, returning the new file descriptor.
+    ///
+    /// # Safety
+    ///
+    /// The caller is responsible for closing the returned descriptor.
+    pub unsafe fn open_fd(&self, dirfd: Fd, path: impl AsRef<WasiPath>) -> Result<Fd, Error> {
+        self.check()?;
+        let (base, inheriting) = self.rights();
+        path_open(
+            dirfd,
+            self.lookup_flags(),
+            path,
+            self.oflags(),
+            base,
+            inheriting,
+            self.fd_flags(),
+        )
+    }
+
+    /// Opens the file at `path` relative to `dir`.
+    pub fn open(&self, dir: &Dir, path: impl AsRef<WasiPath>) -> Result<File, Error> {
+        unsafe { Ok(File::from_raw_fd(self.open_fd(dir.as_raw_fd(), path)?)) }
+    }
+
+    /// Opens the directory at `path` relative to `dir`, as if `directory`
+    /// was set.
+    pub fn open_dir(&self, dir: &Dir, path: impl AsRef<WasiPath>) -> Result<Dir, Error> {
+        let mut options = *self;
+        options.directory(true);
+        unsafe { Ok(Dir::from_raw_fd(options.open_fd(dir.as_raw_fd(), path)?)) }
+    }
+}
+
+/// Copies the file at `src_path` in `src_dir` to `dst_path` in `dst_dir`,
+/// replacing the destination if it exists, and returns the number of bytes
+/// copied.
+///
+/// This is `CopyOptions::new().copy(...)`; see [`CopyOptions`] for how the
+/// data and timestamps are copied.
+pub fn copy(
+    src_dir: &Dir,
+    src_path: impl AsRef<WasiPath>,
+    dst_dir: &Dir,
+    dst_path: impl AsRef<WasiPath>,
+) -> Result<FileSize, Error> {
+    CopyOptions::new().copy(src_dir, src_path, dst_dir, dst_path)
+}
+
+/// Copies the rest of `src`, from its current offset, to `dst` at its
+/// current offset, and returns the number of bytes copied.
+///
+/// This is `CopyOptions::new().copy_fd(...)`; see [`CopyOptions`] for how
+/// the data and timestamps are copied.
+pub fn copy_fd(src: &File, dst: &File) -> Result<FileSize, Error> {
+    CopyOptions::new().copy_fd(src, dst)
+}
+
+/// Options for copying a file, used by [`copy`] and [`copy_fd`].
+///
+/// When the source is a regular file, the copy is announced with
+/// `ADVICE_SEQUENTIAL` and, when the destination is a regular file too, the
+/// access and modification times of the source are copied at the end. If
+/// the destination has `FDFLAG_APPEND` set, everything is written in order.
+///
+/// A regular destination either gets the space for the copy allocated up
+/// front with `fd_allocate`, or has blocks of zeros skipped so that they
+/// become holes where the filesystem supports them; allocating would fill
+/// those holes in. Blocks are only skipped when writing past the end of
+/// the destination, as when [`copy`] replaces a file, and
+/// [`sparse`](CopyOptions::sparse) is on, which it is by default. Turn it
+/// off to preallocate instead, which avoids fragmenting the destination
+/// and finds out early if it doesn't fit, at the cost of storing every
+/// zero.
+#[derive(Copy, Clone, Debug)]
+pub struct CopyOptions {
+    sparse: bool,
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        CopyOptions::new()
+    }
+}
+
+impl CopyOptions {
+    /// Creates options for a sparse copy.
+    pub const fn new() -> Self {
+        CopyOptions { sparse: true }
+    }
+
+    /// Skips blocks of zeros written past the end of the destination
+    /// instead of allocating space for the copy. On by default.
+    pub fn sparse(&mut self, sparse: bool) -> &mut Self {
+        self.sparse = sparse;
+        self
+    }
+
+    /// Copies the file at `src_path` in `src_dir` to `dst_path` in
+    /// `dst_dir`, replacing the destination if it exists, and returns the
+    /// number of bytes copied.
+    pub fn copy(
+        &self,
+        src_dir: &Dir,
+        src_path: impl AsRef<WasiPath>,
+        dst_dir: &Dir,
+        dst_path: impl AsRef<WasiPath>,
+    ) -> Result<FileSize, Error> {
+        let src = OpenOptions::new().read(true).open(src_dir, src_path)?;
+        let dst = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(dst_dir, dst_path)?;
+        self.copy_fd(&src, &dst)
+    }
+
+    /// Copies the rest of `src`, from its current offset, to `dst` at its
+    /// current offset, and returns the number of bytes copied.
+    pub fn copy_fd(&self, src: &File, dst: &File) -> Result<FileSize, Error> {
+        let (src_fd, dst_fd) = (src.as_raw_fd(), dst.as_raw_fd());
+        let src_stat = src.metadata()?;
+        let dst_stat = dst.metadata()?;
+        let append = unsafe { fd_fdstat_get(dst_fd)? }.fs_flags & FDFLAG_APPEND != 0;
+
+        // Where the copy starts in `dst`, if the bytes from there on don't
+        // exist yet, so that writing zeros there can be skipped.
+        let mut sparse_start = None;
+        // Where the copy starts in `dst`, if space was allocated for it.
+        let mut allocated_start = None;
+        if src_stat.is_file() {
+            let src_start = unsafe { fd_tell(src_fd)? };
+            let len = src_stat.len().saturating_sub(src_start);
+            // Allocating and advising are only hints, so failures are ignored.
+            let _ = unsafe { fd_advise(src_fd, src_start, len, ADVICE_SEQUENTIAL) };
+            // With `FDFLAG_APPEND`, every write lands at the end of `dst`
+            // whatever its offset, so there is nothing to allocate at or seek
+            // over.
+            if dst_stat.is_file() && !append {
+                let dst_start = unsafe { fd_tell(dst_fd)? };
+                if self.sparse && dst_stat.len() <= dst_start {
+                    // Allocating would fill in the holes.
+                    sparse_start = Some(dst_start);
+                } else if len > 0 && unsafe { fd_allocate(dst_fd, dst_start, len) }.is_ok() {
+                    allocated_start = Some(dst_start);
+                }
+            }
+        }
+
+        let mut buf = [0; 8192];
+        let mut copied: FileSize = 0;
+        let mut skipped = false;
+        loop {
+            let n = match src.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(EINTR) => continue,
+                Err(err) => return Err(err),
+            };
+            let block = &buf[..n];
+            if sparse_start.is_some() && block.iter().all(|&b| b == 0) {
+                unsafe { fd_seek(dst_fd, n as FileDelta, WHENCE_CUR)? };
+                skipped = true;
+            } else {
+                dst.write_all(block)?;
+            }
+            copied += n as FileSize;
+        }
+
+        if let Some(start) = sparse_start {
+            // Extend over a trailing hole.
+            if skipped {
+                unsafe { fd_filestat_set_size(dst_fd, start + copied)? };
+            }
+        }
+        if let Some(start) = allocated_start {
+            // Trim what was allocated but not copied, if `src` shrank.
+            let end = core::cmp::max(start + copied, dst_stat.len());
+            if end < unsafe { fd_filestat_get(dst_fd)? }.st_size {
+                unsafe { fd_filestat_set_size(dst_fd, end)? };
+            }
+        }
+        if src_stat.is_file() && dst_stat.is_file() {
+            let times = FileTimes::new()
+                .set_accessed(src_stat.as_raw().st_atim)
+                .set_modified(src_stat.as_raw().st_mtim);
+            dst.set_times(&times)?;
+        }
+        Ok(copied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_options_check() {
+        assert_eq!(OpenOptions::new().check(), Err(EINVAL));
+        assert_eq!(OpenOptions::new().read(true).check(), Ok(()));
+        assert_eq!(OpenOptions::new().directory(true).check(), Ok(()));
+        assert_eq!(
+            OpenOptions::new().read(true).create(true).check(),
+            Err(EINVAL)
+        );
+        assert_eq!(
+            OpenOptions::new().read(true).truncate(true).check(),
+            Err(EINVAL)
+        );
+        assert_eq!(
+            OpenOptions::new().append(true).truncate(true).check(),
+            Err(EINVAL)
+        );
+        assert_eq!(
+            OpenOptions::new()
+                .append(true)
+                .truncate(true)
+                .create_new(true)
+                .check(),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn open_options_flags() {
+        let options = *OpenOptions::new().write(true).create(true).truncate(true);
+        assert_eq!(options.lookup_flags(), LOOKUP_SYMLINK_FOLLOW);
+        assert_eq!(options.oflags(), O_CREAT | O_TRUNC);
+        assert_eq!(options.fd_flags(), 0);
+
+        let options = *OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .create_new(true);
+        assert_eq!(options.lookup_flags(), 0);
+        assert_eq!(options.oflags(), O_CREAT | O_EXCL);
+
+        let options = *OpenOptions::new()
+            .read(true)
+            .follow_symlinks(false)
+            .directory(true);
+        assert_eq!(options.lookup_flags(), 0);
+        assert_eq!(options.oflags(), O_DIRECTORY);
+
+        let options = *OpenOptions::new().append(true).nonblocking(true).sync(true);
+        assert_eq!(
+            options.fd_flags(),
+            FDFLAG_APPEND | FDFLAG_NONBLOCK | FDFLAG_SYNC
+        );
+    }
+
+    #[test]
+    fn open_options_rights() {
+        assert_eq!(OpenOptions::new().read(true).rights(), (READ_RIGHTS, 0));
+        assert_eq!(OpenOptions::new().append(true).rights(), (WRITE_RIGHTS, 0));
+        assert_eq!(
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .sync(true)
+                .rights(),
+            (READ_RIGHTS | WRITE_RIGHTS | RIGHT_FD_SYNC, 0)
+        );
+        assert_eq!(
+            OpenOptions::new().directory(true).rights(),
+            (DIR_RIGHTS, DIR_RIGHTS | READ_RIGHTS | WRITE_RIGHTS)
+        );
+        let (base, _) = OpenOptions::new().read(true).rights();
+        assert_eq!(base & RIGHT_FD_WRITE, 0);
+    }
+
+    #[test]
+    fn file_times_to_raw() {
+        assert_eq!(FileTimes::new().to_raw(), (0, 0, 0));
+        assert_eq!(
+            FileTimes::new().set_accessed(5).to_raw(),
+            (5, 0, FILESTAT_SET_ATIM)
+        );
+        assert_eq!(
+            FileTimes::new().set_modified_now().to_raw(),
+            (0, 0, FILESTAT_SET_MTIM_NOW)
+        );
+        assert_eq!(
+            FileTimes::new().set_accessed_now().set_modified(7).to_raw(),
+            (0, 7, FILESTAT_SET_ATIM_NOW | FILESTAT_SET_MTIM)
+        );
+        // A later setter replaces an earlier one for the same time.
+        assert_eq!(
+            FileTimes::new()
+                .set_modified(7)
+                .set_modified_now()
+                .set_accessed_now()
+                .set_accessed(5)
+                .to_raw(),
+            (5, 0, FILESTAT_SET_ATIM | FILESTAT_SET_MTIM_NOW)
+        );
+    }
+}
@@ -0,0 +1,42 @@
+//! A `getrandom` backend backed by `random_get`.
+//!
+//! With the `getrandom` feature enabled this crate registers [`fill`] with
+//! `getrandom::register_custom_getrandom!`, so crates such as `uuid` or
+//! `ahash` get their randomness from the host on targets `getrandom` doesn't
+//! support itself. `getrandom` only allows one custom backend per binary, so
+//! don't register another one alongside this feature.
+//!
+//! Errors carry the WASI errno as a custom `getrandom::Error` code, see
+//! [`errno`]. The codes match the ones used by the `rand_core` RNGs.
+
+use super::*;
+use core::convert::TryFrom;
+use core::num::NonZeroU32;
+
+/// Converts a WASI error into a `getrandom::Error`.
+///
+/// The errno is stored as a custom error code, offset from
+/// `getrandom::Error::CUSTOM_START`.
+pub fn to_getrandom_error(err: Error) -> ::getrandom::Error {
+    let code = ::getrandom::Error::CUSTOM_START + u32::from(err.get());
+    // `CUSTOM_START` is nonzero and adding a `u16` doesn't overflow.
+    ::getrandom::Error::from(NonZeroU32::new(code).unwrap())
+}
+
+/// Returns the WASI error that caused `err`, if it came from this module.
+pub fn errno(err: &::getrandom::Error) -> Option<Error> {
+    let errno = err
+        .code()
+        .get()
+        .checked_sub(::getrandom::Error::CUSTOM_START)?;
+    Error::new(u16::try_from(errno).ok()?)
+}
+
+/// Fills `dest` with random bytes from `random_get`.
+///
+/// This is the function registered as the custom `getrandom` backend.
+pub fn fill(dest: &mut [u8]) -> Result<(), ::getrandom::Error> {
+    random_get(dest).map_err(to_getrandom_error)
+}
+
+::getrandom::register_custom_getrandom!(fill);
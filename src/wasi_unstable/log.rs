@@ -0,0 +1,331 @@
+//! A `log` backend writing to the standard error stream.
+//!
+//! Each record is formatted as
+//!
+//! ```text
+//! [2019-10-19T12:34:56.789Z INFO  my_crate::module] message
+//! ```
+//!
+//! with the timestamp taken from `clock_time_get(CLOCK_REALTIME)`, and
+//! written to `STDERR_FD` with a single `fd_write`, so records from several
+//! instances sharing the stream don't interleave. Records longer than 1024
+//! bytes are truncated.
+//!
+//! A [`WasiLogger`] logs everything up to a maximum level. With the `alloc`
+//! feature it can also be configured per target with `RUST_LOG`-style
+//! directives; [`init`] reads them from the `RUST_LOG` environment variable
+//! and installs the logger. Without `alloc`, install a `static` logger with
+//! `log::set_logger` and `log::set_max_level`.
+
+use super::*;
+use ::log::{LevelFilter, Log, Metadata, Record};
+#[cfg(feature = "alloc")]
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::fmt::{self, Write};
+
+/// A `log::Log` implementation writing to `STDERR_FD`.
+pub struct WasiLogger {
+    level: LevelFilter,
+    #[cfg(feature = "alloc")]
+    directives: Vec<Directive>,
+}
+
+/// The level for the targets starting with `name`.
+#[cfg(feature = "alloc")]
+struct Directive {
+    name: String,
+    level: LevelFilter,
+}
+
+impl WasiLogger {
+    /// Creates a logger logging records up to `level` for all targets.
+    pub const fn new(level: LevelFilter) -> Self {
+        WasiLogger {
+            level,
+            #[cfg(feature = "alloc")]
+            directives: Vec::new(),
+        }
+    }
+
+    /// Creates a logger configured by the `RUST_LOG` environment variable.
+    ///
+    /// Only errors are logged if the variable is unset or can't be read.
+    #[cfg(feature = "alloc")]
+    pub fn from_env() -> Self {
+        let mut logger = WasiLogger::new(LevelFilter::Error);
+        let env = super::env::Env::get();
+        if let Some(spec) = env.ok().and_then(|env| env.var(b"RUST_LOG")) {
+            logger.parse(&String::from_utf8_lossy(spec));
+        }
+        logger
+    }
+
+    /// Adds the directives in `spec`, in the format used by `RUST_LOG`.
+    ///
+    /// `spec` is a comma-separated list of `level`, `target` or
+    /// `target=level` directives. A bare level sets the level for all
+    /// targets, and a bare target enables all its records. Targets match
+    /// by prefix, and the longest matching one applies. Directives that
+    /// don't parse, and filter regexes after a `/`, are ignored.
+    #[cfg(feature = "alloc")]
+    pub fn parse(&mut self, spec: &str) -> &mut Self {
+        let spec = spec.split('/').next().unwrap_or("");
+        for directive in spec.split(',').map(str::trim) {
+            if directive.is_empty() {
+                continue;
+            }
+            let mut parts = directive.splitn(2, '=');
+            let name = parts.next().unwrap_or("").trim();
+            match parts.next().map(str::trim) {
+                Some(level) => {
+                    if let Ok(level) = level.parse() {
+                        self.target(name, level);
+                    }
+                }
+                None => match name.parse() {
+                    Ok(level) => self.level = level,
+                    Err(_) => {
+                        self.target(name, LevelFilter::Trace);
+                    }
+                },
+            }
+        }
+        self
+    }
+
+    /// Sets the level for the targets starting with `name`.
+    #[cfg(feature = "alloc")]
+    pub fn target(&mut self, name: &str, level: LevelFilter) -> &mut Self {
+        match self.directives.iter_mut().find(|d| d.name == name) {
+            Some(directive) => directive.level = level,
+            None => self.directives.push(Directive {
+                name: name.into(),
+                level,
+            }),
+        }
+        self
+    }
+
+    /// Returns the most verbose level this logger logs for any target.
+    ///
+    /// Pass this to `log::set_max_level` when installing the logger.
+    pub fn max_level(&self) -> LevelFilter {
+        #[cfg(feature = "alloc")]
+        {
+            self.directives
+                .iter()
+                .map(|d| d.level)
+                .fold(self.level, core::cmp::max)
+        }
+        #[cfg(not(feature = "alloc"))]
+        {
+            self.level
+        }
+    }
+
+    /// Returns the level records for `target` are logged up to.
+    fn level(&self, target: &str) -> LevelFilter {
+        #[cfg(feature = "alloc")]
+        {
+            self.directives
+                .iter()
+                .filter(|d| target.starts_with(d.name.as_str()))
+                .max_by_key(|d| d.name.len())
+                .map_or(self.level, |d| d.level)
+        }
+        #[cfg(not(feature = "alloc"))]
+        {
+            let _ = target;
+            self.level
+        }
+    }
+
+    /// Installs this logger as the global logger.
+    #[cfg(feature = "alloc")]
+    pub fn init(self) -> Result<(), ::log::SetLoggerError> {
+        let max_level = self.max_level();
+        ::log::set_logger(Box::leak(Box::new(self)))?;
+        ::log::set_max_level(max_level);
+        Ok(())
+    }
+}
+
+impl Default for WasiLogger {
+    fn default() -> Self {
+        WasiLogger::new(LevelFilter::Error)
+    }
+}
+
+/// Installs a [`WasiLogger`] configured by the `RUST_LOG` environment
+/// variable as the global logger.
+#[cfg(feature = "alloc")]
+pub fn init() -> Result<(), ::log::SetLoggerError> {
+    WasiLogger::from_env().init()
+}
+
+impl Log for WasiLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut buf = RecordBuf::new();
+        let _ = buf.write_char('[');
+        if let Ok(now) = clock_time_get(CLOCK_REALTIME, 1_000_000) {
+            let _ = write!(buf, "{} ", Rfc3339(now));
+        }
+        let _ = write!(
+            buf,
+            "{:<5} {}] {}",
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        let _ = stdio::write_all(STDERR_FD, buf.finish());
+    }
+
+    fn flush(&self) {}
+}
+
+const RECORD_BUF_LEN: usize = 1024;
+const ELLIPSIS: &str = "...\n";
+
+/// A fixed-size buffer a record is formatted into, truncating it if needed.
+struct RecordBuf {
+    buf: [u8; RECORD_BUF_LEN],
+    len: usize,
+    truncated: bool,
+}
+
+impl RecordBuf {
+    fn new() -> Self {
+        RecordBuf {
+            buf: [0; RECORD_BUF_LEN],
+            len: 0,
+            truncated: false,
+        }
+    }
+
+    /// Terminates the record with a newline and returns its bytes.
+    fn finish(&mut self) -> &[u8] {
+        if self.write_str("\n").is_err() {
+            let end = self.len + ELLIPSIS.len();
+            self.buf[self.len..end].copy_from_slice(ELLIPSIS.as_bytes());
+            self.len = end;
+        }
+        &self.buf[..self.len]
+    }
+}
+
+impl fmt::Write for RecordBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.truncated {
+            return Err(fmt::Error);
+        }
+        // Keep room for the ellipsis marking a truncated record.
+        let room = RECORD_BUF_LEN - ELLIPSIS.len() - self.len;
+        let mut n = core::cmp::min(s.len(), room);
+        while !s.is_char_boundary(n) {
+            n -= 1;
+        }
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        if n < s.len() {
+            self.truncated = true;
+            return Err(fmt::Error);
+        }
+        Ok(())
+    }
+}
+
+/// Formats a `CLOCK_REALTIME` timestamp as an RFC 3339 UTC date and time
+/// with millisecond precision.
+struct Rfc3339(Timestamp);
+
+impl fmt::Display for Rfc3339 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let secs = self.0 / 1_000_000_000;
+        let millis = self.0 % 1_000_000_000 / 1_000_000;
+        let (days, secs) = (secs / 86400, secs % 86400);
+
+        // Convert days since the epoch to a civil date, see
+        // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let doe = z % 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            year,
+            month,
+            day,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60,
+            millis
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::ToString;
+
+    fn rfc3339(secs: Timestamp, millis: Timestamp) -> std::string::String {
+        Rfc3339(secs * 1_000_000_000 + millis * 1_000_000 + 999).to_string()
+    }
+
+    #[test]
+    fn rfc3339_range() {
+        assert_eq!(Rfc3339(0).to_string(), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            Rfc3339(Timestamp::MAX).to_string(),
+            "2554-07-21T23:34:33.709Z"
+        );
+    }
+
+    #[test]
+    fn rfc3339_leap_years() {
+        assert_eq!(rfc3339(68_169_600, 0), "1972-02-29T00:00:00.000Z");
+        assert_eq!(rfc3339(951_868_799, 999), "2000-02-29T23:59:59.999Z");
+        assert_eq!(rfc3339(951_868_800, 0), "2000-03-01T00:00:00.000Z");
+        assert_eq!(rfc3339(1_709_208_000, 123), "2024-02-29T12:00:00.123Z");
+        // 2100 is divisible by 100 but not by 400, so it isn't a leap year.
+        assert_eq!(rfc3339(4_107_456_000, 0), "2100-02-28T00:00:00.000Z");
+        assert_eq!(rfc3339(4_107_542_400 - 1, 0), "2100-02-28T23:59:59.000Z");
+        assert_eq!(rfc3339(4_107_542_400, 0), "2100-03-01T00:00:00.000Z");
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn directives() {
+        let mut logger = WasiLogger::new(LevelFilter::Error);
+        logger.parse("warn, a=info, a::b=trace, a::b::c=off, d, e=bogus/filter");
+        assert_eq!(logger.level("x"), LevelFilter::Warn);
+        assert_eq!(logger.level("a"), LevelFilter::Info);
+        assert_eq!(logger.level("a::x"), LevelFilter::Info);
+        assert_eq!(logger.level("a::b::x"), LevelFilter::Trace);
+        assert_eq!(logger.level("a::b::c"), LevelFilter::Off);
+        assert_eq!(logger.level("d"), LevelFilter::Trace);
+        assert_eq!(logger.level("e"), LevelFilter::Warn);
+        assert_eq!(logger.max_level(), LevelFilter::Trace);
+
+        // A later directive for the same target replaces the earlier one,
+        // whatever the order of the other targets.
+        logger.parse("a::b=debug,a=error");
+        assert_eq!(logger.level("a::x"), LevelFilter::Error);
+        assert_eq!(logger.level("a::b::x"), LevelFilter::Debug);
+        assert_eq!(logger.level("a::b::c"), LevelFilter::Off);
+    }
+}
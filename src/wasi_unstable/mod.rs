@@ -4,15 +4,61 @@
 //! are unsafe. In the the top-level module, raw pointer-length pairs are
 //! replaced by Rust slice types, output parameters are converted to normal
 //! return values, names are translated to be more Rust-idiomatic, and the
-//! functions are safe.
+//! functions are safe. Paths are passed as [`path::WasiPath`], which rejects
+//! absolute paths and NUL bytes before they reach the host.
 //!
 //! TODO: Not all functions are covered yet; implement the rest of the API.
 
+#[cfg(feature = "async")]
+pub mod async_io;
+#[cfg(feature = "alloc")]
+pub mod env;
+#[cfg(feature = "async")]
+pub mod executor;
+pub mod fs;
+#[cfg(feature = "getrandom")]
+pub mod getrandom;
+#[cfg(feature = "log")]
+pub mod log;
+#[cfg(feature = "panic-handler")]
+pub mod panic;
+pub mod path;
 pub mod raw;
+#[cfg(feature = "async")]
+pub mod reactor;
+#[cfg(any(feature = "record", feature = "replay"))]
+pub mod record;
+#[cfg(feature = "replay")]
+pub mod replay;
+pub mod rights;
+#[cfg(feature = "rand_core")]
+pub mod rng;
+pub mod socket;
+pub mod stdio;
+#[cfg(feature = "alloc")]
+pub mod tempfile;
+#[cfg(feature = "alloc")]
+pub mod timers;
+#[cfg(feature = "trace")]
+pub mod trace;
+#[cfg(feature = "alloc")]
+pub mod walk;
 
+use self::path::WasiPath;
 use core::mem::MaybeUninit;
 use core::num::NonZeroU16;
+
+// The functions below call the imports through whichever of these is
+// enabled; the recording and replaying shims have the same signatures as the
+// raw imports.
+#[cfg(all(feature = "record", feature = "replay"))]
+compile_error!("the `record` and `replay` features can't be enabled together");
+#[cfg(not(any(feature = "record", feature = "replay")))]
 use raw::*;
+#[cfg(feature = "record")]
+use record::imports::*;
+#[cfg(all(feature = "replay", not(feature = "record")))]
+use replay::imports::*;
 
 pub type Advice = __wasi_advice_t;
 pub type ClockId = __wasi_clockid_t;
@@ -79,6 +125,14 @@ macro_rules! errno_set {
                 NonZeroU16::new_unchecked($raw_const)
             };
         )*
+
+        /// Returns the symbolic name of an error, e.g. `"ENOENT"`.
+        pub fn error_name(err: Error) -> Option<&'static str> {
+            match err {
+                $($safe_const => Some(stringify!($safe_const)),)*
+                _ => None,
+            }
+        }
     };
 }
 
@@ -257,6 +311,26 @@ pub const WHENCE_CUR: Whence = __WASI_WHENCE_CUR;
 pub const WHENCE_END: Whence = __WASI_WHENCE_END;
 pub const WHENCE_SET: Whence = __WASI_WHENCE_SET;
 
+#[cfg(feature = "trace")]
+macro_rules! traced {
+    {$name:ident($($arg:ident: $kind:ident),* $(,)?) $body:block} => {{
+        let ret = $body;
+        trace::record(
+            stringify!($name),
+            &[$((stringify!($arg), trace::arg::$kind(&$arg))),*],
+            &ret,
+        );
+        ret
+    }};
+}
+
+#[cfg(not(feature = "trace"))]
+macro_rules! traced {
+    {$name:ident($($arg:ident: $kind:ident),* $(,)?) $body:block} => {
+        $body
+    };
+}
+
 macro_rules! wrap0 {
     {$f:expr} => {
         if let Some(code) = NonZeroU16::new($f) {
@@ -281,73 +355,125 @@ macro_rules! wrap {
 
 #[inline]
 pub fn clock_res_get(clock_id: ClockId) -> Result<Timestamp, Error> {
-    unsafe {
-        wrap! { __wasi_clock_res_get(clock_id) }
+    traced! {
+        clock_res_get(clock_id: clockid) {
+            unsafe {
+                wrap! { __wasi_clock_res_get(clock_id) }
+            }
+        }
     }
 }
 
 #[inline]
 pub fn clock_time_get(clock_id: ClockId, precision: Timestamp) -> Result<Timestamp, Error> {
-    unsafe {
-        wrap! { __wasi_clock_time_get(clock_id, precision) }
+    traced! {
+        clock_time_get(clock_id: clockid, precision: int) {
+            unsafe {
+                wrap! { __wasi_clock_time_get(clock_id, precision) }
+            }
+        }
     }
 }
 
 #[inline]
 pub unsafe fn fd_pread(fd: Fd, iovs: &[IoVec], offset: FileSize) -> Result<usize, Error> {
-    wrap! { __wasi_fd_pread(fd, iovs.as_ptr(), iovs.len(), offset) }
+    traced! {
+        fd_pread(fd: fd, iovs: iovs, offset: int) {
+            wrap! { __wasi_fd_pread(fd, iovs.as_ptr(), iovs.len(), offset) }
+        }
+    }
 }
 
 #[inline]
 pub unsafe fn fd_pwrite(fd: Fd, iovs: &[CIoVec], offset: FileSize) -> Result<usize, Error> {
-    wrap! { __wasi_fd_pwrite(fd, iovs.as_ptr(), iovs.len(), offset) }
+    traced! {
+        fd_pwrite(fd: fd, iovs: ciovs, offset: int) {
+            wrap! { __wasi_fd_pwrite(fd, iovs.as_ptr(), iovs.len(), offset) }
+        }
+    }
 }
 
 #[inline]
 pub fn random_get(buf: &mut [u8]) -> Result<(), Error> {
-    unsafe {
-        wrap0! { __wasi_random_get(buf.as_mut_ptr(), buf.len()) }
+    traced! {
+        random_get(buf: buf) {
+            unsafe {
+                wrap0! { __wasi_random_get(buf.as_mut_ptr(), buf.len()) }
+            }
+        }
     }
 }
 
 #[inline]
 pub unsafe fn fd_close(fd: Fd) -> Result<(), Error> {
-    wrap0! { __wasi_fd_close(fd) }
+    traced! {
+        fd_close(fd: fd) {
+            wrap0! { __wasi_fd_close(fd) }
+        }
+    }
 }
 
 #[inline]
 pub unsafe fn fd_datasync(fd: Fd) -> Result<(), Error> {
-    wrap0! { __wasi_fd_datasync(fd) }
+    traced! {
+        fd_datasync(fd: fd) {
+            wrap0! { __wasi_fd_datasync(fd) }
+        }
+    }
 }
 
 #[inline]
 pub unsafe fn fd_read(fd: Fd, iovs: &[IoVec]) -> Result<usize, Error> {
-    wrap! { __wasi_fd_read(fd, iovs.as_ptr(), iovs.len()) }
+    traced! {
+        fd_read(fd: fd, iovs: iovs) {
+            wrap! { __wasi_fd_read(fd, iovs.as_ptr(), iovs.len()) }
+        }
+    }
 }
 
 #[inline]
 pub unsafe fn fd_renumber(from: Fd, to: Fd) -> Result<(), Error> {
-    wrap0! { __wasi_fd_renumber(from, to) }
+    traced! {
+        fd_renumber(from: fd, to: fd) {
+            wrap0! { __wasi_fd_renumber(from, to) }
+        }
+    }
 }
 
 #[inline]
 pub unsafe fn fd_seek(fd: Fd, offset: FileDelta, whence: Whence) -> Result<FileSize, Error> {
-    wrap! { __wasi_fd_seek(fd, offset, whence) }
+    traced! {
+        fd_seek(fd: fd, offset: delta, whence: whence) {
+            wrap! { __wasi_fd_seek(fd, offset, whence) }
+        }
+    }
 }
 
 #[inline]
 pub unsafe fn fd_tell(fd: Fd) -> Result<FileSize, Error> {
-    wrap! { __wasi_fd_tell(fd) }
+    traced! {
+        fd_tell(fd: fd) {
+            wrap! { __wasi_fd_tell(fd) }
+        }
+    }
 }
 
 #[inline]
 pub unsafe fn fd_fdstat_get(fd: Fd) -> Result<FdStat, Error> {
-    wrap! { __wasi_fd_fdstat_get(fd) }
+    traced! {
+        fd_fdstat_get(fd: fd) {
+            wrap! { __wasi_fd_fdstat_get(fd) }
+        }
+    }
 }
 
 #[inline]
 pub unsafe fn fd_fdstat_set_flags(fd: Fd, flags: FdFlags) -> Result<(), Error> {
-    wrap0! { __wasi_fd_fdstat_set_flags(fd, flags) }
+    traced! {
+        fd_fdstat_set_flags(fd: fd, flags: fdflags) {
+            wrap0! { __wasi_fd_fdstat_set_flags(fd, flags) }
+        }
+    }
 }
 
 #[inline]
@@ -356,17 +482,29 @@ pub unsafe fn fd_fdstat_set_rights(
     fs_rights_base: Rights,
     fs_rights_inheriting: Rights,
 ) -> Result<(), Error> {
-    wrap0! { __wasi_fd_fdstat_set_rights(fd, fs_rights_base, fs_rights_inheriting) }
+    traced! {
+        fd_fdstat_set_rights(fd: fd, fs_rights_base: rights, fs_rights_inheriting: rights) {
+            wrap0! { __wasi_fd_fdstat_set_rights(fd, fs_rights_base, fs_rights_inheriting) }
+        }
+    }
 }
 
 #[inline]
 pub unsafe fn fd_sync(fd: Fd) -> Result<(), Error> {
-    wrap0! { __wasi_fd_sync(fd) }
+    traced! {
+        fd_sync(fd: fd) {
+            wrap0! { __wasi_fd_sync(fd) }
+        }
+    }
 }
 
 #[inline]
 pub unsafe fn fd_write(fd: Fd, iovs: &[CIoVec]) -> Result<usize, Error> {
-    wrap! { __wasi_fd_write(fd, iovs.as_ptr(), iovs.len()) }
+    traced! {
+        fd_write(fd: fd, iovs: ciovs) {
+            wrap! { __wasi_fd_write(fd, iovs.as_ptr(), iovs.len()) }
+        }
+    }
 }
 
 #[inline]
@@ -376,37 +514,56 @@ pub unsafe fn fd_advise(
     len: FileSize,
     advice: Advice,
 ) -> Result<(), Error> {
-    wrap0! { __wasi_fd_advise(fd, offset, len, advice) }
+    traced! {
+        fd_advise(fd: fd, offset: int, len: int, advice: advice) {
+            wrap0! { __wasi_fd_advise(fd, offset, len, advice) }
+        }
+    }
 }
 
 #[inline]
 pub unsafe fn fd_allocate(fd: Fd, offset: FileSize, len: FileSize) -> Result<(), Error> {
-    wrap0! { __wasi_fd_allocate(fd, offset, len) }
+    traced! {
+        fd_allocate(fd: fd, offset: int, len: int) {
+            wrap0! { __wasi_fd_allocate(fd, offset, len) }
+        }
+    }
 }
 
 #[inline]
-pub unsafe fn path_create_directory(fd: Fd, path: &[u8]) -> Result<(), Error> {
-    wrap0! { __wasi_path_create_directory(fd, path.as_ptr(), path.len()) }
+pub unsafe fn path_create_directory(fd: Fd, path: impl AsRef<WasiPath>) -> Result<(), Error> {
+    let path = path.as_ref().as_bytes();
+    traced! {
+        path_create_directory(fd: fd, path: path) {
+            wrap0! { __wasi_path_create_directory(fd, path.as_ptr(), path.len()) }
+        }
+    }
 }
 
 #[inline]
 pub unsafe fn path_link(
     old_fd: Fd,
     old_flags: LookupFlags,
-    old_path: &[u8],
+    old_path: impl AsRef<WasiPath>,
     new_fd: Fd,
-    new_path: &[u8],
+    new_path: impl AsRef<WasiPath>,
 ) -> Result<(), Error> {
-    wrap0! {
-        __wasi_path_link(
-            old_fd,
-            old_flags,
-            old_path.as_ptr(),
-            old_path.len(),
-            new_fd,
-            new_path.as_ptr(),
-            new_path.len(),
-        )
+    let old_path = old_path.as_ref().as_bytes();
+    let new_path = new_path.as_ref().as_bytes();
+    traced! {
+        path_link(old_fd: fd, old_flags: lookupflags, old_path: path, new_fd: fd, new_path: path) {
+            wrap0! {
+                __wasi_path_link(
+                    old_fd,
+                    old_flags,
+                    old_path.as_ptr(),
+                    old_path.len(),
+                    new_fd,
+                    new_path.as_ptr(),
+                    new_path.len(),
+                )
+            }
+        }
     }
 }
 
@@ -414,61 +571,97 @@ pub unsafe fn path_link(
 pub unsafe fn path_open(
     dirfd: Fd,
     dirflags: LookupFlags,
-    path: &[u8],
+    path: impl AsRef<WasiPath>,
     oflags: OFlags,
     fs_rights_base: Rights,
     fs_rights_inheriting: Rights,
     fs_flags: FdFlags,
 ) -> Result<Fd, Error> {
-    wrap! {
-        __wasi_path_open(
-            dirfd,
-            dirflags,
-            path.as_ptr(),
-            path.len(),
-            oflags,
-            fs_rights_base,
-            fs_rights_inheriting,
-            fs_flags,
-        )
+    let path = path.as_ref().as_bytes();
+    traced! {
+        path_open(
+            dirfd: fd,
+            dirflags: lookupflags,
+            path: path,
+            oflags: oflags,
+            fs_rights_base: rights,
+            fs_rights_inheriting: rights,
+            fs_flags: fdflags,
+        ) {
+            wrap! {
+                __wasi_path_open(
+                    dirfd,
+                    dirflags,
+                    path.as_ptr(),
+                    path.len(),
+                    oflags,
+                    fs_rights_base,
+                    fs_rights_inheriting,
+                    fs_flags,
+                )
+            }
+        }
     }
 }
 
 #[inline]
 pub unsafe fn fd_readdir(fd: Fd, buf: &mut [u8], cookie: DirCookie) -> Result<usize, Error> {
-    wrap! { __wasi_fd_readdir(fd, buf.as_mut_ptr(), buf.len(), cookie) }
+    traced! {
+        fd_readdir(fd: fd, buf: buf, cookie: int) {
+            wrap! { __wasi_fd_readdir(fd, buf.as_mut_ptr(), buf.len(), cookie) }
+        }
+    }
 }
 
 #[inline]
-pub unsafe fn path_readlink(fd: Fd, path: &[u8], buf: &mut [u8]) -> Result<usize, Error> {
-    let ptr = buf.as_mut_ptr();
-    wrap! {
-        __wasi_path_readlink(fd, path.as_ptr(), path.len(), ptr, buf.len())
+pub unsafe fn path_readlink(
+    fd: Fd,
+    path: impl AsRef<WasiPath>,
+    buf: &mut [u8],
+) -> Result<usize, Error> {
+    let path = path.as_ref().as_bytes();
+    traced! {
+        path_readlink(fd: fd, path: path, buf: buf) {
+            let ptr = buf.as_mut_ptr();
+            wrap! {
+                __wasi_path_readlink(fd, path.as_ptr(), path.len(), ptr, buf.len())
+            }
+        }
     }
 }
 
 #[inline]
 pub unsafe fn path_rename(
     old_fd: Fd,
-    old_path: &[u8],
+    old_path: impl AsRef<WasiPath>,
     new_fd: Fd,
-    new_path: &[u8],
+    new_path: impl AsRef<WasiPath>,
 ) -> Result<(), Error> {
-    wrap0! {
-        __wasi_path_rename(
-            old_fd,
-            old_path.as_ptr(),
-            old_path.len(),
-            new_fd,
-            new_path.as_ptr(),
-            new_path.len(),
-        )
+    let old_path = old_path.as_ref().as_bytes();
+    let new_path = new_path.as_ref().as_bytes();
+    traced! {
+        path_rename(old_fd: fd, old_path: path, new_fd: fd, new_path: path) {
+            wrap0! {
+                __wasi_path_rename(
+                    old_fd,
+                    old_path.as_ptr(),
+                    old_path.len(),
+                    new_fd,
+                    new_path.as_ptr(),
+                    new_path.len(),
+                )
+            }
+        }
     }
 }
 
 #[inline]
 pub unsafe fn fd_filestat_get(fd: Fd) -> Result<FileStat, Error> {
-    wrap! { __wasi_fd_filestat_get(fd) }
+    traced! {
+        fd_filestat_get(fd: fd) {
+            wrap! { __wasi_fd_filestat_get(fd) }
+        }
+    }
 }
 
 #[inline]
@@ -478,22 +671,35 @@ pub unsafe fn fd_filestat_set_times(
     st_mtim: Timestamp,
     fstflags: FstFlags,
 ) -> Result<(), Error> {
-    wrap0! { __wasi_fd_filestat_set_times(fd, st_atim, st_mtim, fstflags) }
+    traced! {
+        fd_filestat_set_times(fd: fd, st_atim: int, st_mtim: int, fstflags: fstflags) {
+            wrap0! { __wasi_fd_filestat_set_times(fd, st_atim, st_mtim, fstflags) }
+        }
+    }
 }
 
 #[inline]
 pub unsafe fn fd_filestat_set_size(fd: Fd, st_size: FileSize) -> Result<(), Error> {
-    wrap0! { __wasi_fd_filestat_set_size(fd, st_size) }
+    traced! {
+        fd_filestat_set_size(fd: fd, st_size: int) {
+            wrap0! { __wasi_fd_filestat_set_size(fd, st_size) }
+        }
+    }
 }
 
 #[inline]
 pub unsafe fn path_filestat_get(
     fd: Fd,
     flags: LookupFlags,
-    path: &[u8],
+    path: impl AsRef<WasiPath>,
 ) -> Result<FileStat, Error> {
-    wrap! {
-        __wasi_path_filestat_get(fd, flags, path.as_ptr(), path.len())
+    let path = path.as_ref().as_bytes();
+    traced! {
+        path_filestat_get(fd: fd, flags: lookupflags, path: path) {
+            wrap! {
+                __wasi_path_filestat_get(fd, flags, path.as_ptr(), path.len())
+            }
+        }
     }
 }
 
@@ -501,113 +707,186 @@ pub unsafe fn path_filestat_get(
 pub unsafe fn path_filestat_set_times(
     fd: Fd,
     flags: LookupFlags,
-    path: &[u8],
+    path: impl AsRef<WasiPath>,
     st_atim: Timestamp,
     st_mtim: Timestamp,
     fstflags: FstFlags,
 ) -> Result<(), Error> {
-    wrap0! {
-        __wasi_path_filestat_set_times(
-            fd,
-            flags,
-            path.as_ptr(),
-            path.len(),
-            st_atim,
-            st_mtim,
-            fstflags,
-        )
+    let path = path.as_ref().as_bytes();
+    traced! {
+        path_filestat_set_times(
+            fd: fd,
+            flags: lookupflags,
+            path: path,
+            st_atim: int,
+            st_mtim: int,
+            fstflags: fstflags,
+        ) {
+            wrap0! {
+                __wasi_path_filestat_set_times(
+                    fd,
+                    flags,
+                    path.as_ptr(),
+                    path.len(),
+                    st_atim,
+                    st_mtim,
+                    fstflags,
+                )
+            }
+        }
     }
 }
 
 #[inline]
-pub unsafe fn path_symlink(old_path: &[u8], fd: Fd, new_path: &[u8]) -> Result<(), Error> {
-    wrap0! {
-        __wasi_path_symlink(
-            old_path.as_ptr(),
-            old_path.len(),
-            fd,
-            new_path.as_ptr(),
-            new_path.len(),
-        )
+pub unsafe fn path_symlink(
+    old_path: impl AsRef<WasiPath>,
+    fd: Fd,
+    new_path: impl AsRef<WasiPath>,
+) -> Result<(), Error> {
+    let old_path = old_path.as_ref().as_bytes();
+    let new_path = new_path.as_ref().as_bytes();
+    traced! {
+        path_symlink(old_path: path, fd: fd, new_path: path) {
+            wrap0! {
+                __wasi_path_symlink(
+                    old_path.as_ptr(),
+                    old_path.len(),
+                    fd,
+                    new_path.as_ptr(),
+                    new_path.len(),
+                )
+            }
+        }
     }
 }
 
 #[inline]
-pub unsafe fn path_unlink_file(fd: Fd, path: &[u8]) -> Result<(), Error> {
-    wrap0! { __wasi_path_unlink_file(fd, path.as_ptr(), path.len()) }
+pub unsafe fn path_unlink_file(fd: Fd, path: impl AsRef<WasiPath>) -> Result<(), Error> {
+    let path = path.as_ref().as_bytes();
+    traced! {
+        path_unlink_file(fd: fd, path: path) {
+            wrap0! { __wasi_path_unlink_file(fd, path.as_ptr(), path.len()) }
+        }
+    }
 }
 
 #[inline]
-pub unsafe fn path_remove_directory(fd: Fd, path: &[u8]) -> Result<(), Error> {
-    wrap0! { __wasi_path_remove_directory(fd, path.as_ptr(), path.len()) }
+pub unsafe fn path_remove_directory(fd: Fd, path: impl AsRef<WasiPath>) -> Result<(), Error> {
+    let path = path.as_ref().as_bytes();
+    traced! {
+        path_remove_directory(fd: fd, path: path) {
+            wrap0! { __wasi_path_remove_directory(fd, path.as_ptr(), path.len()) }
+        }
+    }
 }
 
 #[inline]
 pub unsafe fn poll_oneoff(in_: &[Subscription], out: &mut [Event]) -> Result<usize, Error> {
-    assert!(out.len() >= in_.len());
-    let ptr = out.as_mut_ptr() as *mut __wasi_event_t;
-    wrap! {
-        __wasi_poll_oneoff(
-            in_.as_ptr(),
-            ptr,
-            in_.len(),
-        )
+    traced! {
+        poll_oneoff(in_: subs, out: events) {
+            assert!(out.len() >= in_.len());
+            let ptr = out.as_mut_ptr() as *mut __wasi_event_t;
+            wrap! {
+                __wasi_poll_oneoff(
+                    in_.as_ptr(),
+                    ptr,
+                    in_.len(),
+                )
+            }
+        }
     }
 }
 
 #[inline]
 pub fn proc_exit(rval: ExitCode) -> ! {
+    #[cfg(feature = "trace")]
+    trace::record_noreturn("proc_exit", &[("rval", trace::arg::int(&rval))]);
     unsafe { __wasi_proc_exit(rval) }
 }
 
+#[inline]
+pub fn proc_raise(sig: Signal) -> Result<(), Error> {
+    traced! {
+        proc_raise(sig: signal) {
+            unsafe {
+                wrap0! { __wasi_proc_raise(sig) }
+            }
+        }
+    }
+}
+
 #[inline]
 pub unsafe fn sock_recv(
     sock: Fd,
     ri_data: &[IoVec],
     ri_flags: RiFlags,
 ) -> Result<(usize, RoFlags), Error> {
-    let mut ro_datalen = MaybeUninit::<usize>::uninit();
-    let mut ro_flags = MaybeUninit::<RoFlags>::uninit();
-    let r = __wasi_sock_recv(
-        sock,
-        ri_data.as_ptr(),
-        ri_data.len(),
-        ri_flags,
-        ro_datalen.as_mut_ptr(),
-        ro_flags.as_mut_ptr(),
-    );
-    if let Some(code) = NonZeroU16::new(r) {
-        Err(code)
-    } else {
-        Ok((ro_datalen.assume_init(), ro_flags.assume_init()))
+    traced! {
+        sock_recv(sock: fd, ri_data: iovs, ri_flags: riflags) {
+            let mut ro_datalen = MaybeUninit::<usize>::uninit();
+            let mut ro_flags = MaybeUninit::<RoFlags>::uninit();
+            let r = __wasi_sock_recv(
+                sock,
+                ri_data.as_ptr(),
+                ri_data.len(),
+                ri_flags,
+                ro_datalen.as_mut_ptr(),
+                ro_flags.as_mut_ptr(),
+            );
+            if let Some(code) = NonZeroU16::new(r) {
+                Err(code)
+            } else {
+                Ok((ro_datalen.assume_init(), ro_flags.assume_init()))
+            }
+        }
     }
 }
 
 #[inline]
 pub unsafe fn sock_send(sock: Fd, si_data: &[CIoVec], si_flags: SiFlags) -> Result<usize, Error> {
-    wrap! { __wasi_sock_send(sock, si_data.as_ptr(), si_data.len(), si_flags) }
+    traced! {
+        sock_send(sock: fd, si_data: ciovs, si_flags: siflags) {
+            wrap! { __wasi_sock_send(sock, si_data.as_ptr(), si_data.len(), si_flags) }
+        }
+    }
 }
 
 #[inline]
 pub unsafe fn sock_shutdown(sock: Fd, how: SdFlags) -> Result<(), Error> {
-    wrap0! { __wasi_sock_shutdown(sock, how) }
+    traced! {
+        sock_shutdown(sock: fd, how: sdflags) {
+            wrap0! { __wasi_sock_shutdown(sock, how) }
+        }
+    }
 }
 
 #[inline]
 pub fn sched_yield() -> Result<(), Error> {
-    unsafe {
-        wrap0! { __wasi_sched_yield() }
+    traced! {
+        sched_yield() {
+            unsafe {
+                wrap0! { __wasi_sched_yield() }
+            }
+        }
     }
 }
 
 #[inline]
 pub unsafe fn fd_prestat_get(fd: Fd) -> Result<Prestat, Error> {
-    wrap! { __wasi_fd_prestat_get(fd) }
+    traced! {
+        fd_prestat_get(fd: fd) {
+            wrap! { __wasi_fd_prestat_get(fd) }
+        }
+    }
 }
 
 #[inline]
 pub unsafe fn fd_prestat_dir_name(fd: Fd, path: &mut [u8]) -> Result<(), Error> {
-    wrap0! { __wasi_fd_prestat_dir_name(fd, path.as_mut_ptr(), path.len()) }
+    traced! {
+        fd_prestat_dir_name(fd: fd, path: buf) {
+            wrap0! { __wasi_fd_prestat_dir_name(fd, path.as_mut_ptr(), path.len()) }
+        }
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -633,11 +912,16 @@ pub fn args_sizes_get() -> Result<ArgsSizes, Error> {
         count: 0,
         buf_len: 0,
     };
-    let code = unsafe { __wasi_args_sizes_get(&mut res.count, &mut res.buf_len) };
-    if let Some(err) = NonZeroU16::new(code) {
-        return Err(err);
+    traced! {
+        args_sizes_get() {
+            let code = unsafe { __wasi_args_sizes_get(&mut res.count, &mut res.buf_len) };
+            if let Some(err) = NonZeroU16::new(code) {
+                Err(err)
+            } else {
+                Ok(res)
+            }
+        }
     }
-    Ok(res)
 }
 
 #[cfg(feature = "alloc")]
@@ -649,10 +933,7 @@ pub fn args_get(ars: ArgsSizes, mut process_arg: impl FnMut(&[u8])) -> Result<()
     // https://github.com/rust-lang/rust/issues/48055
     let mut arg_ptrs = vec![core::ptr::null_mut::<u8>(); ars.count];
     let mut arg_buf = vec![0u8; ars.buf_len];
-    let ret = unsafe { __wasi_args_get(arg_ptrs.as_mut_ptr(), arg_buf.as_mut_ptr()) };
-    if let Some(err) = NonZeroU16::new(ret) {
-        return Err(err);
-    }
+    unsafe { args_get_into(arg_ptrs.as_mut_ptr(), arg_buf.as_mut_ptr())? };
 
     for ptr in arg_ptrs {
         for n in 0.. {
@@ -669,6 +950,22 @@ pub fn args_get(ars: ArgsSizes, mut process_arg: impl FnMut(&[u8])) -> Result<()
     Ok(())
 }
 
+/// Calls `__wasi_args_get`, the way `args_get` does, into buffers owned by
+/// the caller.
+///
+/// # Safety
+///
+/// `argv` must have room for as many pointers, and `argv_buf` for as many
+/// bytes, as `args_sizes_get` reported.
+#[cfg(feature = "alloc")]
+pub(crate) unsafe fn args_get_into(argv: *mut *mut u8, argv_buf: *mut u8) -> Result<(), Error> {
+    traced! {
+        args_get() {
+            wrap0! { __wasi_args_get(argv, argv_buf) }
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct EnvironSizes {
     count: usize,
@@ -692,11 +989,16 @@ pub fn environ_sizes_get() -> Result<EnvironSizes, Error> {
         count: 0,
         buf_len: 0,
     };
-    let code = unsafe { __wasi_environ_sizes_get(&mut res.count, &mut res.buf_len) };
-    if let Some(err) = NonZeroU16::new(code) {
-        return Err(err);
+    traced! {
+        environ_sizes_get() {
+            let code = unsafe { __wasi_environ_sizes_get(&mut res.count, &mut res.buf_len) };
+            if let Some(err) = NonZeroU16::new(code) {
+                Err(err)
+            } else {
+                Ok(res)
+            }
+        }
     }
-    Ok(res)
 }
 
 #[cfg(feature = "alloc")]
@@ -711,25 +1013,15 @@ pub fn environ_get(
     // https://github.com/rust-lang/rust/issues/48055
     let mut env_ptrs = vec![core::ptr::null_mut::<u8>(); es.count];
     let mut env_buf = vec![0u8; es.buf_len];
-    let ret = unsafe { __wasi_environ_get(env_ptrs.as_mut_ptr(), env_buf.as_mut_ptr()) };
-    if let Some(err) = NonZeroU16::new(ret) {
-        return Err(err);
-    }
+    unsafe { environ_get_into(env_ptrs.as_mut_ptr(), env_buf.as_mut_ptr())? };
 
     for ptr in env_ptrs {
-        let mut key: &[u8] = &[];
         for n in 0.. {
             unsafe {
-                match *ptr.add(n) {
-                    0 => {
-                        let val = core::slice::from_raw_parts(ptr, n);
-                        process_env(key, val);
-                        break;
-                    }
-                    b'=' if key.is_empty() => {
-                        key = core::slice::from_raw_parts(ptr, n);
-                    }
-                    _ => {}
+                if *ptr.add(n) == 0 {
+                    let (key, val) = split_env(core::slice::from_raw_parts(ptr, n));
+                    process_env(key, val);
+                    break;
                 }
             }
         }
@@ -738,6 +1030,36 @@ pub fn environ_get(
     Ok(())
 }
 
+/// Calls `__wasi_environ_get`, the way `environ_get` does, into buffers
+/// owned by the caller.
+///
+/// # Safety
+///
+/// `environ` must have room for as many pointers, and `environ_buf` for as
+/// many bytes, as `environ_sizes_get` reported.
+#[cfg(feature = "alloc")]
+pub(crate) unsafe fn environ_get_into(
+    environ: *mut *mut u8,
+    environ_buf: *mut u8,
+) -> Result<(), Error> {
+    traced! {
+        environ_get() {
+            wrap0! { __wasi_environ_get(environ, environ_buf) }
+        }
+    }
+}
+
+/// Splits an environment variable into its key and value, at the first `=`
+/// after the first byte. Without one, the key is empty and the value is
+/// the whole variable, so `=foo` is a value rather than an empty key.
+#[cfg(feature = "alloc")]
+pub(crate) fn split_env(var: &[u8]) -> (&[u8], &[u8]) {
+    match var.iter().skip(1).position(|&c| c == b'=') {
+        Some(i) => (&var[..=i], &var[i + 2..]),
+        None => (&[], var),
+    }
+}
+
 pub fn error_str(err: Error) -> Option<&'static str> {
     let desc = match err {
         E2BIG => "Argument list too long",
@@ -820,3 +1142,35 @@ pub fn error_str(err: Error) -> Option<&'static str> {
     };
     Some(desc)
 }
+
+/// Converts an error into a `std::io::Error`.
+///
+/// On WASI targets the result carries the errno as its raw OS error, like
+/// the errors returned by `std`. Elsewhere errnos don't match the host's,
+/// so only the error kind and description are kept.
+#[cfg(feature = "std")]
+pub fn io_error(err: Error) -> std::io::Error {
+    use std::io::{Error as IoError, ErrorKind};
+
+    if cfg!(target_os = "wasi") {
+        return IoError::from_raw_os_error(i32::from(err.get()));
+    }
+    let kind = match err {
+        EACCES | EPERM | ENOTCAPABLE => ErrorKind::PermissionDenied,
+        EADDRINUSE => ErrorKind::AddrInUse,
+        EADDRNOTAVAIL => ErrorKind::AddrNotAvailable,
+        EAGAIN => ErrorKind::WouldBlock,
+        ECONNABORTED => ErrorKind::ConnectionAborted,
+        ECONNREFUSED => ErrorKind::ConnectionRefused,
+        ECONNRESET => ErrorKind::ConnectionReset,
+        EEXIST => ErrorKind::AlreadyExists,
+        EINTR => ErrorKind::Interrupted,
+        EINVAL => ErrorKind::InvalidInput,
+        ENOENT => ErrorKind::NotFound,
+        ENOTCONN => ErrorKind::NotConnected,
+        EPIPE => ErrorKind::BrokenPipe,
+        ETIMEDOUT => ErrorKind::TimedOut,
+        _ => ErrorKind::Other,
+    };
+    IoError::new(kind, error_str(err).unwrap_or("Unknown error"))
+}
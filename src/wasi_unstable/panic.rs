@@ -0,0 +1,81 @@
+//! A panic handler for `no_std` binaries.
+//!
+//! With the `panic-handler` feature enabled this crate provides the
+//! `#[panic_handler]` when targeting wasm, so a `no_std` binary doesn't need
+//! to write its own. The handler prints the panic message and location to
+//! `STDERR_FD` and then terminates the process, by default with
+//! `proc_exit(101)`, the exit code Rust uses for panics. Use [`set_exit`]
+//! to pick another code or to abort with `SIGABRT` instead.
+//!
+//! This can't be combined with `std`, which has a panic handler of its own.
+
+// `std` has a panic handler of its own, and two would fail to link with a
+// far less helpful error.
+#[cfg(all(feature = "panic-handler", feature = "std"))]
+compile_error!("the `panic-handler` and `std` features can't be enabled together");
+
+use super::stdio::{LineWriter, Stderr};
+use super::*;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// How the process is terminated after a panic.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PanicExit {
+    /// Exit with `proc_exit` and the given code.
+    Exit(ExitCode),
+    /// Raise `SIGABRT` with `proc_raise`, see [`abort`].
+    Abort,
+}
+
+static ABORT: AtomicBool = AtomicBool::new(false);
+static EXIT_CODE: AtomicU32 = AtomicU32::new(101);
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// Sets how the process is terminated after a panic.
+pub fn set_exit(exit: PanicExit) {
+    match exit {
+        PanicExit::Exit(code) => {
+            EXIT_CODE.store(code, Ordering::SeqCst);
+            ABORT.store(false, Ordering::SeqCst);
+        }
+        PanicExit::Abort => ABORT.store(true, Ordering::SeqCst),
+    }
+}
+
+/// Returns how the process is terminated after a panic.
+pub fn exit() -> PanicExit {
+    if ABORT.load(Ordering::SeqCst) {
+        PanicExit::Abort
+    } else {
+        PanicExit::Exit(EXIT_CODE.load(Ordering::SeqCst))
+    }
+}
+
+/// Terminates the process abnormally by raising `SIGABRT`.
+///
+/// Should the host return from `proc_raise`, the process exits with the
+/// code configured with [`set_exit`] instead, or 101 if none was.
+pub fn abort() -> ! {
+    let _ = proc_raise(SIGABRT);
+    proc_exit(EXIT_CODE.load(Ordering::SeqCst))
+}
+
+/// Prints `info` to stderr and terminates the process as configured.
+pub fn handle_panic(info: &core::panic::PanicInfo) -> ! {
+    // A panic while printing the message ends up back here; don't try to
+    // print it again.
+    if !PANICKING.swap(true, Ordering::SeqCst) {
+        let _ = writeln!(LineWriter::new(Stderr), "{}", info);
+    }
+    match exit() {
+        PanicExit::Exit(code) => proc_exit(code),
+        PanicExit::Abort => abort(),
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", not(test)))]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    handle_panic(info)
+}
@@ -0,0 +1,492 @@
+//! Paths accepted by the `path_*` functions.
+//!
+//! WASI resolves every path relative to a directory descriptor, so a path
+//! that starts with `/` can never work, and a NUL byte can't be passed to
+//! most hosts' underlying system calls. [`WasiPath`] and its owned
+//! counterpart [`WasiPathBuf`] are byte strings checked for both when they
+//! are created, which the `path_*` functions take instead of plain slices.
+//!
+//! Paths don't have to be UTF-8, but [`WasiPath::to_str`] and
+//! [`WasiPath::display`] make it easy to work with those that are.
+
+use super::*;
+#[cfg(feature = "alloc")]
+use alloc::borrow::{Borrow, ToOwned};
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+#[cfg(feature = "alloc")]
+use core::ops::Deref;
+
+/// A borrowed path, relative and without NUL bytes.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct WasiPath {
+    bytes: [u8],
+}
+
+/// Checks that `bytes` can be used as a path.
+fn validate(bytes: &[u8]) -> Result<(), Error> {
+    if bytes.first() == Some(&b'/') {
+        return Err(ENOTCAPABLE);
+    }
+    if bytes.contains(&0) {
+        return Err(EILSEQ);
+    }
+    Ok(())
+}
+
+impl WasiPath {
+    /// Wraps `path`, failing with `ENOTCAPABLE` if it is absolute and with
+    /// `EILSEQ` if it contains a NUL byte.
+    pub fn new<P: AsRef<[u8]> + ?Sized>(path: &P) -> Result<&WasiPath, Error> {
+        let bytes = path.as_ref();
+        validate(bytes)?;
+        Ok(WasiPath::from_bytes_unchecked(bytes))
+    }
+
+    /// Wraps bytes already known to be a valid path.
+    pub(crate) fn from_bytes_unchecked(bytes: &[u8]) -> &WasiPath {
+        // `WasiPath` is a transparent wrapper around `[u8]`.
+        unsafe { &*(bytes as *const [u8] as *const WasiPath) }
+    }
+
+    /// Returns the path's bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns the path as a string, if it is UTF-8.
+    pub fn to_str(&self) -> Option<&str> {
+        core::str::from_utf8(&self.bytes).ok()
+    }
+
+    /// Returns an object that displays the path, with invalid UTF-8
+    /// replaced by U+FFFD.
+    pub fn display(&self) -> Display<'_> {
+        Display { path: self }
+    }
+
+    /// Returns whether the path is empty.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Returns an iterator over the path's components.
+    ///
+    /// Repeated and trailing `/` are ignored, as are `.` components other
+    /// than a leading one, so `a//./b/` has the components `a` and `b` and
+    /// `./a` has the components `.` and `a`.
+    pub fn components(&self) -> Components<'_> {
+        let (cur_dir, rest) = match &self.bytes {
+            [b'.'] => (true, &[][..]),
+            [b'.', b'/', rest @ ..] => (true, rest),
+            bytes => (false, bytes),
+        };
+        Components {
+            cur_dir,
+            inner: rest.split(is_separator as fn(&u8) -> bool),
+        }
+    }
+
+    /// Returns the last component, unless it is `.` or `..` or there is
+    /// none.
+    pub fn file_name(&self) -> Option<&[u8]> {
+        match self.components().next_back()? {
+            Component::Normal(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Returns the path without its last component, or `None` if it has
+    /// no components. The parent of a single component is the empty path.
+    pub fn parent(&self) -> Option<&WasiPath> {
+        let bytes = trim_end(&self.bytes);
+        if bytes.is_empty() {
+            return None;
+        }
+        let parent = match bytes.iter().rposition(|&c| c == b'/') {
+            Some(i) => trim_end(&bytes[..i]),
+            None => &[],
+        };
+        Some(WasiPath::from_bytes_unchecked(parent))
+    }
+
+    /// Returns `path` appended to this path.
+    #[cfg(feature = "alloc")]
+    pub fn join(&self, path: impl AsRef<WasiPath>) -> WasiPathBuf {
+        let mut buf = self.to_owned();
+        buf.push(path);
+        buf
+    }
+
+    /// Returns the path with `.` components and repeated `/` removed and
+    /// `..` applied to the component before it, or `.` if nothing is left.
+    ///
+    /// This only looks at the path itself, so `a/..` becomes `.` even if
+    /// `a` is a symbolic link. Fails with `ENOTCAPABLE` if a `..` would go
+    /// above the start of the path.
+    #[cfg(feature = "alloc")]
+    pub fn normalize(&self) -> Result<WasiPathBuf, Error> {
+        let mut buf = WasiPathBuf::new();
+        for component in self.components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    if !buf.pop() {
+                        return Err(ENOTCAPABLE);
+                    }
+                }
+                Component::Normal(name) => buf.push(WasiPath::from_bytes_unchecked(name)),
+            }
+        }
+        if buf.is_empty() {
+            buf.bytes.push(b'.');
+        }
+        Ok(buf)
+    }
+}
+
+fn is_separator(c: &u8) -> bool {
+    *c == b'/'
+}
+
+/// Strips the trailing `/` and `.` components that [`WasiPath::components`]
+/// ignores.
+fn trim_end(mut bytes: &[u8]) -> &[u8] {
+    loop {
+        while let [rest @ .., b'/'] = bytes {
+            bytes = rest;
+        }
+        match bytes {
+            [rest @ .., b'/', b'.'] => bytes = rest,
+            _ => return bytes,
+        }
+    }
+}
+
+impl AsRef<WasiPath> for WasiPath {
+    fn as_ref(&self) -> &WasiPath {
+        self
+    }
+}
+
+impl AsRef<[u8]> for WasiPath {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for &'a WasiPath {
+    type Error = Error;
+
+    fn try_from(path: &'a [u8]) -> Result<Self, Error> {
+        WasiPath::new(path)
+    }
+}
+
+impl<'a> TryFrom<&'a str> for &'a WasiPath {
+    type Error = Error;
+
+    fn try_from(path: &'a str) -> Result<Self, Error> {
+        WasiPath::new(path)
+    }
+}
+
+impl fmt::Debug for WasiPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("\"")?;
+        for chunk in self.bytes.utf8_chunks() {
+            for c in chunk.valid().chars() {
+                write!(f, "{}", c.escape_debug())?;
+            }
+            for byte in chunk.invalid() {
+                write!(f, "\\x{:02x}", byte)?;
+            }
+        }
+        f.write_str("\"")
+    }
+}
+
+/// Displays a path, returned by [`WasiPath::display`].
+#[derive(Copy, Clone, Debug)]
+pub struct Display<'a> {
+    path: &'a WasiPath,
+}
+
+impl fmt::Display for Display<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for chunk in self.path.bytes.utf8_chunks() {
+            f.write_str(chunk.valid())?;
+            if !chunk.invalid().is_empty() {
+                f.write_str("\u{fffd}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A component of a path.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Component<'a> {
+    /// A leading `.`.
+    CurDir,
+    /// `..`
+    ParentDir,
+    /// Any other name.
+    Normal(&'a [u8]),
+}
+
+/// An iterator over the components of a path, returned by
+/// [`WasiPath::components`].
+#[derive(Clone, Debug)]
+pub struct Components<'a> {
+    /// Whether the leading `.` hasn't been returned yet.
+    cur_dir: bool,
+    /// The rest of the path, after any leading `.`.
+    inner: core::slice::Split<'a, u8, fn(&u8) -> bool>,
+}
+
+fn is_ignored(bytes: &&[u8]) -> bool {
+    bytes.is_empty() || *bytes == b"."
+}
+
+fn component(bytes: &[u8]) -> Component<'_> {
+    match bytes {
+        b".." => Component::ParentDir,
+        name => Component::Normal(name),
+    }
+}
+
+impl<'a> Iterator for Components<'a> {
+    type Item = Component<'a>;
+
+    fn next(&mut self) -> Option<Component<'a>> {
+        if self.cur_dir {
+            self.cur_dir = false;
+            return Some(Component::CurDir);
+        }
+        self.inner.find(|c| !is_ignored(c)).map(component)
+    }
+}
+
+impl<'a> DoubleEndedIterator for Components<'a> {
+    fn next_back(&mut self) -> Option<Component<'a>> {
+        match self.inner.rfind(|c| !is_ignored(c)) {
+            Some(c) => Some(component(c)),
+            None if self.cur_dir => {
+                self.cur_dir = false;
+                Some(Component::CurDir)
+            }
+            None => None,
+        }
+    }
+}
+
+/// An owned path, relative and without NUL bytes.
+#[cfg(feature = "alloc")]
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WasiPathBuf {
+    bytes: Vec<u8>,
+}
+
+#[cfg(feature = "alloc")]
+impl WasiPathBuf {
+    /// Creates an empty path.
+    pub fn new() -> Self {
+        WasiPathBuf::default()
+    }
+
+    /// Wraps `bytes`, failing with `ENOTCAPABLE` if they are an absolute
+    /// path and with `EILSEQ` if they contain a NUL byte.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, Error> {
+        validate(&bytes)?;
+        Ok(WasiPathBuf { bytes })
+    }
+
+    /// Wraps bytes already known to be a valid path.
+    pub(crate) fn from_bytes_unchecked(bytes: Vec<u8>) -> Self {
+        WasiPathBuf { bytes }
+    }
+
+    /// Returns the path's bytes, consuming the path.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Returns the path as a `WasiPath`.
+    pub fn as_path(&self) -> &WasiPath {
+        WasiPath::from_bytes_unchecked(&self.bytes)
+    }
+
+    /// Appends `path`, separated by a `/` unless this path is empty or
+    /// already ends with one.
+    pub fn push(&mut self, path: impl AsRef<WasiPath>) {
+        let path = path.as_ref().as_bytes();
+        if path.is_empty() {
+            return;
+        }
+        if !self.bytes.is_empty() && self.bytes.last() != Some(&b'/') {
+            self.bytes.push(b'/');
+        }
+        self.bytes.extend_from_slice(path);
+    }
+
+    /// Removes the last component, returning whether there was one.
+    pub fn pop(&mut self) -> bool {
+        match self.parent().map(|parent| parent.bytes.len()) {
+            Some(len) => {
+                self.bytes.truncate(len);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Deref for WasiPathBuf {
+    type Target = WasiPath;
+
+    fn deref(&self) -> &WasiPath {
+        self.as_path()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl AsRef<WasiPath> for WasiPathBuf {
+    fn as_ref(&self) -> &WasiPath {
+        self.as_path()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl AsRef<[u8]> for WasiPathBuf {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Borrow<WasiPath> for WasiPathBuf {
+    fn borrow(&self) -> &WasiPath {
+        self.as_path()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl ToOwned for WasiPath {
+    type Owned = WasiPathBuf;
+
+    fn to_owned(&self) -> WasiPathBuf {
+        WasiPathBuf::from_bytes_unchecked(self.bytes.to_vec())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl From<&WasiPath> for WasiPathBuf {
+    fn from(path: &WasiPath) -> Self {
+        path.to_owned()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl TryFrom<Vec<u8>> for WasiPathBuf {
+    type Error = Error;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self, Error> {
+        WasiPathBuf::from_bytes(bytes)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl TryFrom<alloc::string::String> for WasiPathBuf {
+    type Error = Error;
+
+    fn try_from(path: alloc::string::String) -> Result<Self, Error> {
+        WasiPathBuf::from_bytes(path.into_bytes())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl fmt::Debug for WasiPathBuf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_path(), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    fn path(s: &str) -> &WasiPath {
+        WasiPath::new(s).unwrap()
+    }
+
+    #[test]
+    fn validate() {
+        assert_eq!(WasiPath::new("/abs").err(), Some(ENOTCAPABLE));
+        assert_eq!(WasiPath::new("a\0b").err(), Some(EILSEQ));
+        assert_eq!(path("a/b").as_bytes(), b"a/b");
+        assert!(path("").is_empty());
+    }
+
+    #[test]
+    fn components() {
+        let components = |s| path(s).components().collect::<Vec<_>>();
+        use Component::*;
+        assert_eq!(components("a//b/"), [Normal(b"a"), Normal(b"b")]);
+        assert_eq!(components("a/./b/."), [Normal(b"a"), Normal(b"b")]);
+        assert_eq!(components("./a/.."), [CurDir, Normal(b"a"), ParentDir]);
+        assert_eq!(components("."), [CurDir]);
+        assert_eq!(components(""), []);
+        let back = path(".//a//").components().rev().collect::<Vec<_>>();
+        assert_eq!(back, [Normal(b"a"), CurDir]);
+    }
+
+    #[test]
+    fn parent_and_file_name() {
+        let parent = |s| path(s).parent().map(WasiPath::as_bytes);
+        assert_eq!(parent("a/b"), Some(&b"a"[..]));
+        assert_eq!(parent("a//b//"), Some(&b"a"[..]));
+        assert_eq!(parent("a/./b"), Some(&b"a"[..]));
+        assert_eq!(parent("a/."), Some(&b""[..]));
+        assert_eq!(parent("a"), Some(&b""[..]));
+        assert_eq!(parent("./a"), Some(&b"."[..]));
+        assert_eq!(parent(""), None);
+
+        assert_eq!(path("a/b/").file_name(), Some(&b"b"[..]));
+        assert_eq!(path("a/.").file_name(), Some(&b"a"[..]));
+        assert_eq!(path("a/..").file_name(), None);
+        assert_eq!(path("").file_name(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn push_and_pop() {
+        let mut buf = WasiPathBuf::new();
+        buf.push(path("a"));
+        buf.push(path("b/"));
+        buf.push(path("c"));
+        buf.push(path(""));
+        assert_eq!(buf.as_bytes(), b"a/b/c");
+        assert!(buf.pop());
+        assert_eq!(buf.as_bytes(), b"a/b");
+        assert!(buf.pop());
+        assert!(buf.pop());
+        assert!(buf.is_empty());
+        assert!(!buf.pop());
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn normalize() {
+        let normalize = |s| path(s).normalize().map(WasiPathBuf::into_bytes);
+        assert_eq!(normalize("a/.."), Ok(b".".to_vec()));
+        assert_eq!(normalize("./a//./b/../c/"), Ok(b"a/c".to_vec()));
+        assert_eq!(normalize("../x"), Err(ENOTCAPABLE));
+        assert_eq!(normalize("a/../.."), Err(ENOTCAPABLE));
+    }
+}
@@ -0,0 +1,343 @@
+//! A reactor turning `poll_oneoff` events into task wake-ups.
+//!
+//! Futures waiting for a file descriptor to become readable or writable, or
+//! for a clock to reach a deadline, hold a [`Registration`]. While a task is
+//! waiting on it, the registration contributes a `Subscription` to the next
+//! [`turn`] of the reactor, which calls `poll_oneoff` and wakes the tasks
+//! whose subscriptions produced an event.
+//!
+//! Clock registrations are kept in a [`Timers`] per clock, so a turn only
+//! subscribes to the nearest deadline on each clock.
+//!
+//! The executor in the `executor` module turns the reactor whenever it has
+//! no tasks to run; code driving futures some other way has to call [`turn`]
+//! itself.
+
+use super::raw::{
+    __wasi_subscription_clock_t, __wasi_subscription_fd_readwrite_t, __wasi_subscription_u,
+};
+use super::timers::Timers;
+use super::*;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+
+/// A lock for the executor's and reactor's global state.
+///
+/// WASI programs are single-threaded, so the lock is never contended, and
+/// finding it held means the state was accessed re-entrantly, which is a bug.
+pub(crate) struct Lock<T> {
+    locked: AtomicBool,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Lock<T> {}
+
+pub(crate) struct LockGuard<'a, T> {
+    lock: &'a Lock<T>,
+}
+
+impl<T> Lock<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        Lock {
+            locked: AtomicBool::new(false),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub(crate) fn lock(&self) -> LockGuard<'_, T> {
+        if self.locked.swap(true, Ordering::Acquire) {
+            panic!("async runtime state accessed re-entrantly");
+        }
+        LockGuard { lock: self }
+    }
+}
+
+impl<T> Deref for LockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for LockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for LockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+    }
+}
+
+/// The userdata of the subscription that keeps a non-blocking turn from
+/// blocking.
+const NO_WAIT: Userdata = u64::MAX;
+
+struct Entry {
+    token: Userdata,
+    subscription: Subscription,
+    waker: Option<Waker>,
+    event: Option<Event>,
+}
+
+impl Entry {
+    /// Whether a task is waiting for an event that hasn't happened yet.
+    fn armed(&self) -> bool {
+        self.waker.is_some() && self.event.is_none()
+    }
+
+    /// Records `event` for the entry, returning the waker to wake.
+    fn fire(&mut self, event: Event) -> Option<Waker> {
+        if self.event.is_some() {
+            return None;
+        }
+        self.event = Some(event);
+        self.waker.clone()
+    }
+}
+
+struct State {
+    next_token: Userdata,
+    entries: Vec<Entry>,
+    /// The deadlines of the clock registrations, one `Timers` per clock.
+    timers: Vec<Timers>,
+}
+
+impl State {
+    fn entry(&mut self, token: Userdata) -> Option<&mut Entry> {
+        self.entries.iter_mut().find(|e| e.token == token)
+    }
+
+    /// Fires the due clock registrations on the clock whose nearest
+    /// deadline belongs to `token`.
+    fn expire(&mut self, token: Userdata, wakers: &mut Vec<Waker>) {
+        let State {
+            entries, timers, ..
+        } = self;
+        let timers = match timers
+            .iter_mut()
+            .find(|t| t.next().map(|n| n.0) == Some(token))
+        {
+            Some(timers) => timers,
+            None => return,
+        };
+        let mut fire = |token, error| {
+            if let Some(entry) = entries.iter_mut().find(|e| e.token == token) {
+                wakers.extend(entry.fire(event_for(token, EVENTTYPE_CLOCK, error)));
+            }
+        };
+        if let Err(err) = timers.expire_now(|token| fire(token, 0)) {
+            fire(token, err.get());
+        }
+    }
+}
+
+static STATE: Lock<State> = Lock::new(State {
+    next_token: 0,
+    entries: Vec::new(),
+    timers: Vec::new(),
+});
+
+/// Returns an event as reported by `poll_oneoff`.
+fn event_for(userdata: Userdata, r#type: EventType, error: Errno) -> Event {
+    let mut event: Event = unsafe { core::mem::zeroed() };
+    event.userdata = userdata;
+    event.r#type = r#type;
+    event.error = error;
+    event
+}
+
+/// Interest in an event, registered with the reactor until dropped.
+#[derive(Debug)]
+pub struct Registration {
+    token: Userdata,
+}
+
+impl Registration {
+    fn new(state: &mut State, r#type: EventType, u: __wasi_subscription_u) -> Self {
+        let token = state.next_token;
+        state.next_token += 1;
+        state.entries.push(Entry {
+            token,
+            subscription: Subscription {
+                userdata: token,
+                r#type,
+                u,
+            },
+            waker: None,
+            event: None,
+        });
+        Registration { token }
+    }
+
+    /// Registers interest in `fd` becoming readable.
+    pub fn fd_read(fd: Fd) -> Self {
+        let u = __wasi_subscription_u {
+            fd_readwrite: __wasi_subscription_fd_readwrite_t {
+                file_descriptor: fd,
+            },
+        };
+        Registration::new(&mut STATE.lock(), EVENTTYPE_FD_READ, u)
+    }
+
+    /// Registers interest in `fd` becoming writable.
+    pub fn fd_write(fd: Fd) -> Self {
+        let u = __wasi_subscription_u {
+            fd_readwrite: __wasi_subscription_fd_readwrite_t {
+                file_descriptor: fd,
+            },
+        };
+        Registration::new(&mut STATE.lock(), EVENTTYPE_FD_WRITE, u)
+    }
+
+    /// Registers interest in `clock_id` reaching the absolute time
+    /// `deadline`.
+    pub fn clock(clock_id: ClockId, deadline: Timestamp) -> Self {
+        let u = __wasi_subscription_u {
+            clock: __wasi_subscription_clock_t {
+                identifier: 0,
+                clock_id,
+                timeout: deadline,
+                precision: 0,
+                flags: SUBSCRIPTION_CLOCK_ABSTIME,
+            },
+        };
+        let mut state = STATE.lock();
+        let registration = Registration::new(&mut state, EVENTTYPE_CLOCK, u);
+        let index = match state.timers.iter().position(|t| t.clock_id() == clock_id) {
+            Some(index) => index,
+            None => {
+                state.timers.push(Timers::new(clock_id));
+                state.timers.len() - 1
+            }
+        };
+        state.timers[index].insert(registration.token, deadline);
+        registration
+    }
+
+    /// Returns the userdata of this registration's subscription.
+    pub fn token(&self) -> Userdata {
+        self.token
+    }
+
+    /// Takes the event reported for this registration since it was last
+    /// taken, or arranges for the task to be woken when there is one.
+    ///
+    /// An event carrying an error is returned as `Err`.
+    pub fn poll_ready(&self, cx: &mut Context) -> Poll<Result<Event, Error>> {
+        let mut state = STATE.lock();
+        let entry = state.entry(self.token).expect("registration not found");
+        match entry.event.take() {
+            Some(event) => {
+                entry.waker = None;
+                Poll::Ready(match Error::new(event.error) {
+                    Some(err) => Err(err),
+                    None => Ok(event),
+                })
+            }
+            None => {
+                match &entry.waker {
+                    Some(waker) if waker.will_wake(cx.waker()) => {}
+                    _ => entry.waker = Some(cx.waker().clone()),
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut state = STATE.lock();
+        state.entries.retain(|e| e.token != self.token);
+        for timers in &mut state.timers {
+            timers.cancel(self.token);
+        }
+    }
+}
+
+/// Returns whether any task is waiting for an event.
+pub fn is_waiting() -> bool {
+    STATE.lock().entries.iter().any(Entry::armed)
+}
+
+/// Polls for the events tasks are waiting for and wakes those tasks.
+///
+/// If `block` is true, this waits until at least one event happens;
+/// otherwise it only picks up the events that already happened. Returns
+/// the number of tasks woken, which is 0 right away if no task is waiting.
+///
+/// If `poll_oneoff` fails, every waiting task is woken and receives the
+/// error from [`Registration::poll_ready`].
+pub fn turn(block: bool) -> Result<usize, Error> {
+    let mut subscriptions: Vec<Subscription> = {
+        let state = STATE.lock();
+        if !state.entries.iter().any(Entry::armed) {
+            return Ok(0);
+        }
+        state
+            .entries
+            .iter()
+            .filter(|e| e.armed() && e.subscription.r#type != EVENTTYPE_CLOCK)
+            .map(|e| e.subscription)
+            .chain(state.timers.iter().filter_map(Timers::subscription))
+            .collect()
+    };
+    if !block {
+        subscriptions.push(Subscription {
+            userdata: NO_WAIT,
+            r#type: EVENTTYPE_CLOCK,
+            u: __wasi_subscription_u {
+                clock: __wasi_subscription_clock_t {
+                    identifier: 0,
+                    clock_id: CLOCK_MONOTONIC,
+                    timeout: 0,
+                    precision: 0,
+                    flags: 0,
+                },
+            },
+        });
+    }
+
+    let mut events: Vec<Event> = Vec::with_capacity(subscriptions.len());
+    events.resize(subscriptions.len(), event_for(0, 0, 0));
+    let result = unsafe { poll_oneoff(&subscriptions, &mut events) };
+
+    let mut wakers = Vec::new();
+    {
+        let mut state = STATE.lock();
+        match result {
+            Ok(n) => {
+                for event in &events[..n] {
+                    if event.userdata == NO_WAIT {
+                        continue;
+                    }
+                    if event.r#type == EVENTTYPE_CLOCK && event.error == 0 {
+                        state.expire(event.userdata, &mut wakers);
+                    } else if let Some(entry) = state.entry(event.userdata) {
+                        wakers.extend(entry.fire(*event));
+                    }
+                }
+            }
+            Err(err) => {
+                // Report the failure to every waiting task.
+                for entry in state.entries.iter_mut().filter(|e| e.armed()) {
+                    let event = event_for(entry.token, entry.subscription.r#type, err.get());
+                    wakers.extend(entry.fire(event));
+                }
+            }
+        }
+    }
+    // Wake outside the lock, wakers may use the reactor.
+    let woken = wakers.len();
+    for waker in wakers {
+        waker.wake();
+    }
+    result.map(|_| woken)
+}
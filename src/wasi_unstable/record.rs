@@ -0,0 +1,1087 @@
+//! Recording of the calls made to the `wasi_unstable` imports.
+//!
+//! With the `record` feature enabled, the wrappers in the parent module call
+//! the imports through shims which, once [`start`] has been called, append
+//! an entry for every call to a log. Each entry holds everything the host
+//! handed back to the guest: return values, bytes read, directory entries,
+//! random data, clock readings and so on. With the `replay` feature the same
+//! log can be fed back to the guest, see the `replay` module.
+//!
+//! The log is a sequence of entries, each of which is laid out as
+//!
+//! | size | contents                                        |
+//! |------|-------------------------------------------------|
+//! | 1    | the function, as a [`Func`]                     |
+//! | 2    | the errno returned by the host, little-endian   |
+//! | 4    | the length of the payload, little-endian        |
+//! | n    | the payload                                     |
+//!
+//! The payload is empty when the call failed. Otherwise it holds the output
+//! parameters of the call in order: sizes and other integers as
+//! little-endian `u64`s, structures field by field, and buffers filled in by
+//! the host as their used bytes.
+
+use super::raw::*;
+#[cfg(feature = "record")]
+use core::convert::TryFrom;
+use core::fmt;
+
+macro_rules! funcs {
+    ($($variant:ident = $name:ident,)*) => {
+        /// The import an entry in a log was recorded for.
+        #[repr(u8)]
+        #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+        pub enum Func {
+            $($variant,)*
+        }
+
+        const FUNCS: &[Func] = &[$(Func::$variant,)*];
+
+        impl Func {
+            /// Returns the name of the import, e.g. `"fd_read"`.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Func::$variant => stringify!($name),)*
+                }
+            }
+
+            fn from_u8(b: u8) -> Option<Func> {
+                FUNCS.get(usize::from(b)).cloned()
+            }
+        }
+    };
+}
+
+funcs! {
+    ArgsGet = args_get,
+    ArgsSizesGet = args_sizes_get,
+    EnvironGet = environ_get,
+    EnvironSizesGet = environ_sizes_get,
+    ClockResGet = clock_res_get,
+    ClockTimeGet = clock_time_get,
+    FdAdvise = fd_advise,
+    FdAllocate = fd_allocate,
+    FdClose = fd_close,
+    FdDatasync = fd_datasync,
+    FdFdstatGet = fd_fdstat_get,
+    FdFdstatSetFlags = fd_fdstat_set_flags,
+    FdFdstatSetRights = fd_fdstat_set_rights,
+    FdFilestatGet = fd_filestat_get,
+    FdFilestatSetSize = fd_filestat_set_size,
+    FdFilestatSetTimes = fd_filestat_set_times,
+    FdPread = fd_pread,
+    FdPrestatGet = fd_prestat_get,
+    FdPrestatDirName = fd_prestat_dir_name,
+    FdPwrite = fd_pwrite,
+    FdRead = fd_read,
+    FdReaddir = fd_readdir,
+    FdRenumber = fd_renumber,
+    FdSeek = fd_seek,
+    FdSync = fd_sync,
+    FdTell = fd_tell,
+    FdWrite = fd_write,
+    PathCreateDirectory = path_create_directory,
+    PathFilestatGet = path_filestat_get,
+    PathFilestatSetTimes = path_filestat_set_times,
+    PathLink = path_link,
+    PathOpen = path_open,
+    PathReadlink = path_readlink,
+    PathRemoveDirectory = path_remove_directory,
+    PathRename = path_rename,
+    PathSymlink = path_symlink,
+    PathUnlinkFile = path_unlink_file,
+    PollOneoff = poll_oneoff,
+    ProcExit = proc_exit,
+    ProcRaise = proc_raise,
+    SchedYield = sched_yield,
+    RandomGet = random_get,
+    SockRecv = sock_recv,
+    SockSend = sock_send,
+    SockShutdown = sock_shutdown,
+}
+
+impl fmt::Display for Func {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+const HEADER_LEN: usize = 7;
+
+/// A single entry of a log.
+#[derive(Copy, Clone, Debug)]
+pub struct Entry<'a> {
+    /// The import which was called.
+    pub func: Func,
+    /// The errno returned by the host.
+    pub errno: __wasi_errno_t,
+    /// The output parameters of the call, see the module documentation.
+    pub payload: &'a [u8],
+}
+
+/// An iterator over the entries of a log.
+///
+/// Iteration stops at the first malformed or truncated entry; use
+/// [`Entries::remainder`] to find out whether the whole log was consumed.
+pub struct Entries<'a> {
+    log: &'a [u8],
+}
+
+/// Returns an iterator over the entries of a recorded log.
+pub fn entries(log: &[u8]) -> Entries<'_> {
+    Entries { log }
+}
+
+impl<'a> Entries<'a> {
+    /// Returns the part of the log which hasn't been iterated over yet.
+    pub fn remainder(&self) -> &'a [u8] {
+        self.log
+    }
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Entry<'a>;
+
+    fn next(&mut self) -> Option<Entry<'a>> {
+        if self.log.len() < HEADER_LEN {
+            return None;
+        }
+        let func = Func::from_u8(self.log[0])?;
+        let errno = u16::from_le_bytes([self.log[1], self.log[2]]);
+        let len = u32::from_le_bytes([self.log[3], self.log[4], self.log[5], self.log[6]]) as usize;
+        let end = HEADER_LEN.checked_add(len)?;
+        let payload = self.log.get(HEADER_LEN..end)?;
+        self.log = &self.log[end..];
+        Some(Entry {
+            func,
+            errno,
+            payload,
+        })
+    }
+}
+
+#[cfg(feature = "record")]
+pub(crate) const FDSTAT_LEN: usize = 19;
+#[cfg(feature = "record")]
+pub(crate) const FILESTAT_LEN: usize = 53;
+#[cfg(feature = "record")]
+pub(crate) const PRESTAT_LEN: usize = 9;
+#[cfg(feature = "record")]
+pub(crate) const EVENT_LEN: usize = 21;
+
+#[cfg(feature = "record")]
+/// Encodes values into a fixed-size little-endian buffer.
+pub(crate) struct Encoder<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+#[cfg(feature = "record")]
+impl<'a> Encoder<'a> {
+    pub(crate) fn new(buf: &'a mut [u8]) -> Self {
+        Encoder { buf, pos: 0 }
+    }
+
+    pub(crate) fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.buf[self.pos..self.pos + bytes.len()].copy_from_slice(bytes);
+        self.pos += bytes.len();
+        self
+    }
+
+    pub(crate) fn fdstat(&mut self, stat: &__wasi_fdstat_t) -> &mut Self {
+        self.bytes(&[stat.fs_filetype])
+            .bytes(&stat.fs_flags.to_le_bytes())
+            .bytes(&stat.fs_rights_base.to_le_bytes())
+            .bytes(&stat.fs_rights_inheriting.to_le_bytes())
+    }
+
+    pub(crate) fn filestat(&mut self, stat: &__wasi_filestat_t) -> &mut Self {
+        self.bytes(&stat.st_dev.to_le_bytes())
+            .bytes(&stat.st_ino.to_le_bytes())
+            .bytes(&[stat.st_filetype])
+            .bytes(&stat.st_nlink.to_le_bytes())
+            .bytes(&stat.st_size.to_le_bytes())
+            .bytes(&stat.st_atim.to_le_bytes())
+            .bytes(&stat.st_mtim.to_le_bytes())
+            .bytes(&stat.st_ctim.to_le_bytes())
+    }
+
+    pub(crate) fn prestat(&mut self, stat: &__wasi_prestat_t) -> &mut Self {
+        let name_len = unsafe { stat.u.dir.pr_name_len } as u64;
+        self.bytes(&[stat.pr_type]).bytes(&name_len.to_le_bytes())
+    }
+
+    pub(crate) fn event(&mut self, event: &__wasi_event_t) -> &mut Self {
+        let rw = unsafe { event.u.fd_readwrite };
+        self.bytes(&event.userdata.to_le_bytes())
+            .bytes(&event.error.to_le_bytes())
+            .bytes(&[event.r#type])
+            .bytes(&rw.nbytes.to_le_bytes())
+            .bytes(&rw.flags.to_le_bytes())
+    }
+}
+
+#[cfg(feature = "replay")]
+/// Decodes values from a little-endian buffer, the inverse of `Encoder`.
+pub(crate) struct Decoder<'a> {
+    func: Func,
+    buf: &'a [u8],
+}
+
+#[cfg(feature = "replay")]
+impl<'a> Decoder<'a> {
+    /// Decodes `buf`, the payload of an entry for `func`.
+    pub(crate) fn new(func: Func, buf: &'a [u8]) -> Self {
+        Decoder { func, buf }
+    }
+
+    /// Takes the next `n` bytes, panicking if the payload is too short for
+    /// what `func` returns.
+    pub(crate) fn bytes(&mut self, n: usize) -> &'a [u8] {
+        if n > self.buf.len() {
+            panic!(
+                "replay: {} needs {} more bytes but only {} were recorded at this point",
+                self.func,
+                n,
+                self.buf.len()
+            );
+        }
+        let (head, tail) = self.buf.split_at(n);
+        self.buf = tail;
+        head
+    }
+
+    fn array<const N: usize>(&mut self) -> [u8; N] {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N));
+        array
+    }
+
+    /// Takes everything that hasn't been decoded yet.
+    pub(crate) fn rest(&mut self) -> &'a [u8] {
+        self.bytes(self.buf.len())
+    }
+
+    pub(crate) fn u8(&mut self) -> u8 {
+        self.bytes(1)[0]
+    }
+
+    pub(crate) fn u16(&mut self) -> u16 {
+        u16::from_le_bytes(self.array())
+    }
+
+    pub(crate) fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.array())
+    }
+
+    pub(crate) fn u64(&mut self) -> u64 {
+        u64::from_le_bytes(self.array())
+    }
+
+    pub(crate) fn fdstat(&mut self) -> __wasi_fdstat_t {
+        __wasi_fdstat_t {
+            fs_filetype: self.u8(),
+            fs_flags: self.u16(),
+            fs_rights_base: self.u64(),
+            fs_rights_inheriting: self.u64(),
+        }
+    }
+
+    pub(crate) fn filestat(&mut self) -> __wasi_filestat_t {
+        __wasi_filestat_t {
+            st_dev: self.u64(),
+            st_ino: self.u64(),
+            st_filetype: self.u8(),
+            st_nlink: self.u32(),
+            st_size: self.u64(),
+            st_atim: self.u64(),
+            st_mtim: self.u64(),
+            st_ctim: self.u64(),
+        }
+    }
+
+    pub(crate) fn prestat(&mut self) -> __wasi_prestat_t {
+        __wasi_prestat_t {
+            pr_type: self.u8(),
+            u: __wasi_prestat_u {
+                dir: __wasi_prestat_dir {
+                    pr_name_len: self.u64() as usize,
+                },
+            },
+        }
+    }
+
+    pub(crate) fn event(&mut self) -> __wasi_event_t {
+        __wasi_event_t {
+            userdata: self.u64(),
+            error: self.u16(),
+            r#type: self.u8(),
+            u: __wasi_event_u {
+                fd_readwrite: __wasi_event_fd_readwrite_t {
+                    nbytes: self.u64(),
+                    flags: self.u16(),
+                },
+            },
+        }
+    }
+}
+
+#[cfg(feature = "record")]
+pub use self::recorder::{start, stop};
+
+#[cfg(feature = "record")]
+mod recorder {
+    use super::*;
+    use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+    static RECORDING: AtomicBool = AtomicBool::new(false);
+    static LOG_FD: AtomicU32 = AtomicU32::new(0);
+
+    /// Starts appending an entry for every call to an import to `fd`.
+    ///
+    /// To be replayable a log has to start before the guest makes its first
+    /// call, so this is best called first thing in `main`. Writes to `fd`
+    /// itself are not recorded; if one of them fails recording stops.
+    pub fn start(fd: __wasi_fd_t) {
+        LOG_FD.store(fd, Ordering::SeqCst);
+        RECORDING.store(true, Ordering::SeqCst);
+    }
+
+    /// Stops recording calls.
+    pub fn stop() {
+        RECORDING.store(false, Ordering::SeqCst);
+    }
+
+    fn write_all(fd: __wasi_fd_t, mut data: &[u8]) -> bool {
+        while !data.is_empty() {
+            let iov = __wasi_ciovec_t {
+                buf: data.as_ptr(),
+                buf_len: data.len(),
+            };
+            let mut nwritten = 0;
+            let r = unsafe { __wasi_fd_write(fd, &iov, 1, &mut nwritten) };
+            if r != __WASI_ESUCCESS || nwritten == 0 {
+                return false;
+            }
+            data = &data[nwritten..];
+        }
+        true
+    }
+
+    /// An entry being appended to the log.
+    ///
+    /// Dropping it after a write failed stops recording.
+    pub(crate) struct EntryWriter {
+        fd: __wasi_fd_t,
+        ok: bool,
+    }
+
+    impl EntryWriter {
+        /// Writes the header of an entry with a payload of `len` bytes,
+        /// unless recording is off.
+        ///
+        /// A payload too long for the header stops recording, like a failed
+        /// write, since the log couldn't be replayed past it.
+        pub(crate) fn begin(func: Func, errno: __wasi_errno_t, len: usize) -> Option<Self> {
+            if !RECORDING.load(Ordering::SeqCst) {
+                return None;
+            }
+            let len = match u32::try_from(len) {
+                Ok(len) => len,
+                Err(_) => {
+                    stop();
+                    return None;
+                }
+            };
+            let mut header = [0; HEADER_LEN];
+            Encoder::new(&mut header)
+                .bytes(&[func as u8])
+                .bytes(&errno.to_le_bytes())
+                .bytes(&len.to_le_bytes());
+            let mut writer = EntryWriter {
+                fd: LOG_FD.load(Ordering::SeqCst),
+                ok: true,
+            };
+            writer.write(&header);
+            Some(writer)
+        }
+
+        pub(crate) fn write(&mut self, data: &[u8]) {
+            if self.ok {
+                self.ok = write_all(self.fd, data);
+            }
+        }
+    }
+
+    impl Drop for EntryWriter {
+        fn drop(&mut self) {
+            if !self.ok {
+                stop();
+            }
+        }
+    }
+
+    /// Appends an entry whose payload is the concatenation of `parts`.
+    pub(crate) fn emit(func: Func, errno: __wasi_errno_t, parts: &[&[u8]]) {
+        let len = parts.iter().map(|p| p.len()).sum();
+        if let Some(mut w) = EntryWriter::begin(func, errno, len) {
+            for part in parts {
+                w.write(part);
+            }
+        }
+    }
+
+    /// Appends an entry for a successful call whose payload is `prefix`
+    /// followed by the first `n` bytes held by `iovs`.
+    pub(crate) unsafe fn emit_iovs(
+        func: Func,
+        prefix: &[u8],
+        iovs: *const __wasi_iovec_t,
+        iovs_len: usize,
+        n: usize,
+    ) {
+        if let Some(mut w) = EntryWriter::begin(func, __WASI_ESUCCESS, prefix.len() + n) {
+            w.write(prefix);
+            let mut left = n;
+            for iov in core::slice::from_raw_parts(iovs, iovs_len) {
+                let len = iov.buf_len.min(left);
+                w.write(core::slice::from_raw_parts(iov.buf, len));
+                left -= len;
+            }
+        }
+    }
+}
+
+/// The shims the parent module calls instead of the raw imports.
+#[cfg(feature = "record")]
+pub(crate) mod imports {
+    use super::super::raw;
+    pub use super::super::raw::*;
+    use super::recorder::{emit, emit_iovs, EntryWriter};
+    use super::{Encoder, Func};
+    use super::{EVENT_LEN, FDSTAT_LEN, FILESTAT_LEN, PRESTAT_LEN};
+
+    /// Records a call whose only output is its errno.
+    fn done(func: Func, r: __wasi_errno_t) -> __wasi_errno_t {
+        emit(func, r, &[]);
+        r
+    }
+
+    /// Records a call which outputs a single integer on success. `v` reads
+    /// it, and is only called on success, since the host leaves the output
+    /// unwritten otherwise.
+    fn value(func: Func, r: __wasi_errno_t, v: impl FnOnce() -> u64) -> __wasi_errno_t {
+        if r == __WASI_ESUCCESS {
+            emit(func, r, &[&v().to_le_bytes()]);
+        } else {
+            emit(func, r, &[]);
+        }
+        r
+    }
+
+    /// Records a call which fills in `buf` on success.
+    fn filled(func: Func, r: __wasi_errno_t, buf: &[u8]) -> __wasi_errno_t {
+        if r == __WASI_ESUCCESS {
+            emit(func, r, &[buf]);
+        } else {
+            emit(func, r, &[]);
+        }
+        r
+    }
+
+    // Only `args_get` and `environ_get` use this, which need `alloc`.
+    #[cfg_attr(not(feature = "alloc"), allow(dead_code))]
+    unsafe fn strings(
+        func: Func,
+        r: __wasi_errno_t,
+        sizes_get: unsafe extern "C" fn(*mut usize, *mut usize) -> __wasi_errno_t,
+        buf: *mut u8,
+    ) -> __wasi_errno_t {
+        // The size of the buffer isn't passed in, so ask the host again.
+        let (mut count, mut buf_len) = (0, 0);
+        if r != __WASI_ESUCCESS || sizes_get(&mut count, &mut buf_len) != __WASI_ESUCCESS {
+            return done(func, r);
+        }
+        filled(func, r, core::slice::from_raw_parts(buf, buf_len))
+    }
+
+    unsafe fn sizes(func: Func, r: __wasi_errno_t, a: *mut usize, b: *mut usize) -> __wasi_errno_t {
+        if r == __WASI_ESUCCESS {
+            emit(
+                func,
+                r,
+                &[&(*a as u64).to_le_bytes(), &(*b as u64).to_le_bytes()],
+            );
+        } else {
+            emit(func, r, &[]);
+        }
+        r
+    }
+
+    pub unsafe fn __wasi_args_get(argv: *mut *mut u8, argv_buf: *mut u8) -> __wasi_errno_t {
+        let r = raw::__wasi_args_get(argv, argv_buf);
+        strings(Func::ArgsGet, r, raw::__wasi_args_sizes_get, argv_buf)
+    }
+
+    pub unsafe fn __wasi_args_sizes_get(
+        argc: *mut usize,
+        argv_buf_size: *mut usize,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_args_sizes_get(argc, argv_buf_size);
+        sizes(Func::ArgsSizesGet, r, argc, argv_buf_size)
+    }
+
+    pub unsafe fn __wasi_environ_get(
+        environ: *mut *mut u8,
+        environ_buf: *mut u8,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_environ_get(environ, environ_buf);
+        strings(
+            Func::EnvironGet,
+            r,
+            raw::__wasi_environ_sizes_get,
+            environ_buf,
+        )
+    }
+
+    pub unsafe fn __wasi_environ_sizes_get(
+        argc: *mut usize,
+        argv_buf_size: *mut usize,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_environ_sizes_get(argc, argv_buf_size);
+        sizes(Func::EnvironSizesGet, r, argc, argv_buf_size)
+    }
+
+    pub unsafe fn __wasi_clock_res_get(
+        clock_id: __wasi_clockid_t,
+        resolution: *mut __wasi_timestamp_t,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_clock_res_get(clock_id, resolution);
+        value(Func::ClockResGet, r, || *resolution)
+    }
+
+    pub unsafe fn __wasi_clock_time_get(
+        clock_id: __wasi_clockid_t,
+        precision: __wasi_timestamp_t,
+        time: *mut __wasi_timestamp_t,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_clock_time_get(clock_id, precision, time);
+        value(Func::ClockTimeGet, r, || *time)
+    }
+
+    pub unsafe fn __wasi_fd_advise(
+        fd: __wasi_fd_t,
+        offset: __wasi_filesize_t,
+        len: __wasi_filesize_t,
+        advice: __wasi_advice_t,
+    ) -> __wasi_errno_t {
+        done(
+            Func::FdAdvise,
+            raw::__wasi_fd_advise(fd, offset, len, advice),
+        )
+    }
+
+    pub unsafe fn __wasi_fd_allocate(
+        fd: __wasi_fd_t,
+        offset: __wasi_filesize_t,
+        len: __wasi_filesize_t,
+    ) -> __wasi_errno_t {
+        done(Func::FdAllocate, raw::__wasi_fd_allocate(fd, offset, len))
+    }
+
+    pub unsafe fn __wasi_fd_close(fd: __wasi_fd_t) -> __wasi_errno_t {
+        done(Func::FdClose, raw::__wasi_fd_close(fd))
+    }
+
+    pub unsafe fn __wasi_fd_datasync(fd: __wasi_fd_t) -> __wasi_errno_t {
+        done(Func::FdDatasync, raw::__wasi_fd_datasync(fd))
+    }
+
+    pub unsafe fn __wasi_fd_fdstat_get(
+        fd: __wasi_fd_t,
+        stat: *mut __wasi_fdstat_t,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_fd_fdstat_get(fd, stat);
+        let mut buf = [0; FDSTAT_LEN];
+        if r == __WASI_ESUCCESS {
+            Encoder::new(&mut buf).fdstat(&*stat);
+        }
+        filled(Func::FdFdstatGet, r, &buf)
+    }
+
+    pub unsafe fn __wasi_fd_fdstat_set_flags(
+        fd: __wasi_fd_t,
+        flags: __wasi_fdflags_t,
+    ) -> __wasi_errno_t {
+        done(
+            Func::FdFdstatSetFlags,
+            raw::__wasi_fd_fdstat_set_flags(fd, flags),
+        )
+    }
+
+    pub unsafe fn __wasi_fd_fdstat_set_rights(
+        fd: __wasi_fd_t,
+        fs_rights_base: __wasi_rights_t,
+        fs_rights_inheriting: __wasi_rights_t,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_fd_fdstat_set_rights(fd, fs_rights_base, fs_rights_inheriting);
+        done(Func::FdFdstatSetRights, r)
+    }
+
+    pub unsafe fn __wasi_fd_filestat_get(
+        fd: __wasi_fd_t,
+        buf: *mut __wasi_filestat_t,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_fd_filestat_get(fd, buf);
+        let mut out = [0; FILESTAT_LEN];
+        if r == __WASI_ESUCCESS {
+            Encoder::new(&mut out).filestat(&*buf);
+        }
+        filled(Func::FdFilestatGet, r, &out)
+    }
+
+    pub unsafe fn __wasi_fd_filestat_set_size(
+        fd: __wasi_fd_t,
+        st_size: __wasi_filesize_t,
+    ) -> __wasi_errno_t {
+        done(
+            Func::FdFilestatSetSize,
+            raw::__wasi_fd_filestat_set_size(fd, st_size),
+        )
+    }
+
+    pub unsafe fn __wasi_fd_filestat_set_times(
+        fd: __wasi_fd_t,
+        st_atim: __wasi_timestamp_t,
+        st_mtim: __wasi_timestamp_t,
+        fst_flags: __wasi_fstflags_t,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_fd_filestat_set_times(fd, st_atim, st_mtim, fst_flags);
+        done(Func::FdFilestatSetTimes, r)
+    }
+
+    pub unsafe fn __wasi_fd_pread(
+        fd: __wasi_fd_t,
+        iovs_ptr: *const __wasi_iovec_t,
+        iovs_len: usize,
+        offset: __wasi_filesize_t,
+        nread: *mut __wasi_size_t,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_fd_pread(fd, iovs_ptr, iovs_len, offset, nread);
+        if r == __WASI_ESUCCESS {
+            emit_iovs(Func::FdPread, &[], iovs_ptr, iovs_len, *nread);
+            r
+        } else {
+            done(Func::FdPread, r)
+        }
+    }
+
+    pub unsafe fn __wasi_fd_prestat_get(
+        fd: __wasi_fd_t,
+        buf: *mut __wasi_prestat_t,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_fd_prestat_get(fd, buf);
+        let mut out = [0; PRESTAT_LEN];
+        if r == __WASI_ESUCCESS {
+            Encoder::new(&mut out).prestat(&*buf);
+        }
+        filled(Func::FdPrestatGet, r, &out)
+    }
+
+    pub unsafe fn __wasi_fd_prestat_dir_name(
+        fd: __wasi_fd_t,
+        path: *mut u8,
+        path_len: __wasi_size_t,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_fd_prestat_dir_name(fd, path, path_len);
+        let name = core::slice::from_raw_parts(path, path_len);
+        filled(Func::FdPrestatDirName, r, name)
+    }
+
+    pub unsafe fn __wasi_fd_pwrite(
+        fd: __wasi_fd_t,
+        iovs_ptr: *const __wasi_ciovec_t,
+        iovs_len: usize,
+        offset: __wasi_filesize_t,
+        nwritten: *mut __wasi_size_t,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_fd_pwrite(fd, iovs_ptr, iovs_len, offset, nwritten);
+        value(Func::FdPwrite, r, || *nwritten as u64)
+    }
+
+    pub unsafe fn __wasi_fd_read(
+        fd: __wasi_fd_t,
+        iovs_ptr: *const __wasi_iovec_t,
+        iovs_len: usize,
+        nread: *mut __wasi_size_t,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_fd_read(fd, iovs_ptr, iovs_len, nread);
+        if r == __WASI_ESUCCESS {
+            emit_iovs(Func::FdRead, &[], iovs_ptr, iovs_len, *nread);
+            r
+        } else {
+            done(Func::FdRead, r)
+        }
+    }
+
+    pub unsafe fn __wasi_fd_readdir(
+        fd: __wasi_fd_t,
+        buf: *mut u8,
+        buf_len: __wasi_size_t,
+        cookie: __wasi_dircookie_t,
+        bufused: *mut __wasi_size_t,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_fd_readdir(fd, buf, buf_len, cookie, bufused);
+        if r == __WASI_ESUCCESS {
+            filled(
+                Func::FdReaddir,
+                r,
+                core::slice::from_raw_parts(buf, *bufused),
+            )
+        } else {
+            done(Func::FdReaddir, r)
+        }
+    }
+
+    pub unsafe fn __wasi_fd_renumber(fd: __wasi_fd_t, to: __wasi_fd_t) -> __wasi_errno_t {
+        done(Func::FdRenumber, raw::__wasi_fd_renumber(fd, to))
+    }
+
+    pub unsafe fn __wasi_fd_seek(
+        fd: __wasi_fd_t,
+        offset: __wasi_filedelta_t,
+        whence: __wasi_whence_t,
+        newoffset: *mut __wasi_filesize_t,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_fd_seek(fd, offset, whence, newoffset);
+        value(Func::FdSeek, r, || *newoffset)
+    }
+
+    pub unsafe fn __wasi_fd_sync(fd: __wasi_fd_t) -> __wasi_errno_t {
+        done(Func::FdSync, raw::__wasi_fd_sync(fd))
+    }
+
+    pub unsafe fn __wasi_fd_tell(
+        fd: __wasi_fd_t,
+        offset: *mut __wasi_filesize_t,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_fd_tell(fd, offset);
+        value(Func::FdTell, r, || *offset)
+    }
+
+    pub unsafe fn __wasi_fd_write(
+        fd: __wasi_fd_t,
+        iovs_ptr: *const __wasi_ciovec_t,
+        iovs_len: usize,
+        nwritten: *mut __wasi_size_t,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_fd_write(fd, iovs_ptr, iovs_len, nwritten);
+        value(Func::FdWrite, r, || *nwritten as u64)
+    }
+
+    pub unsafe fn __wasi_path_create_directory(
+        fd: __wasi_fd_t,
+        path_ptr: *const u8,
+        path_len: usize,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_path_create_directory(fd, path_ptr, path_len);
+        done(Func::PathCreateDirectory, r)
+    }
+
+    pub unsafe fn __wasi_path_filestat_get(
+        fd: __wasi_fd_t,
+        flags: __wasi_lookupflags_t,
+        path_ptr: *const u8,
+        path_len: usize,
+        buf: *mut __wasi_filestat_t,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_path_filestat_get(fd, flags, path_ptr, path_len, buf);
+        let mut out = [0; FILESTAT_LEN];
+        if r == __WASI_ESUCCESS {
+            Encoder::new(&mut out).filestat(&*buf);
+        }
+        filled(Func::PathFilestatGet, r, &out)
+    }
+
+    pub unsafe fn __wasi_path_filestat_set_times(
+        fd: __wasi_fd_t,
+        flags: __wasi_lookupflags_t,
+        path_ptr: *const u8,
+        path_len: usize,
+        st_atim: __wasi_timestamp_t,
+        st_mtim: __wasi_timestamp_t,
+        fst_flags: __wasi_fstflags_t,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_path_filestat_set_times(
+            fd, flags, path_ptr, path_len, st_atim, st_mtim, fst_flags,
+        );
+        done(Func::PathFilestatSetTimes, r)
+    }
+
+    pub unsafe fn __wasi_path_link(
+        old_fd: __wasi_fd_t,
+        old_flags: __wasi_lookupflags_t,
+        old_path_ptr: *const u8,
+        old_path_len: usize,
+        new_fd: __wasi_fd_t,
+        new_path_ptr: *const u8,
+        new_path_len: usize,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_path_link(
+            old_fd,
+            old_flags,
+            old_path_ptr,
+            old_path_len,
+            new_fd,
+            new_path_ptr,
+            new_path_len,
+        );
+        done(Func::PathLink, r)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn __wasi_path_open(
+        fd: __wasi_fd_t,
+        dirflags: __wasi_lookupflags_t,
+        path_ptr: *const u8,
+        path_len: usize,
+        o_flags: __wasi_oflags_t,
+        fs_rights_base: __wasi_rights_t,
+        fs_rights_inherting: __wasi_rights_t,
+        flags: __wasi_fdflags_t,
+        opened_fd: *mut __wasi_fd_t,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_path_open(
+            fd,
+            dirflags,
+            path_ptr,
+            path_len,
+            o_flags,
+            fs_rights_base,
+            fs_rights_inherting,
+            flags,
+            opened_fd,
+        );
+        value(Func::PathOpen, r, || u64::from(*opened_fd))
+    }
+
+    pub unsafe fn __wasi_path_readlink(
+        fd: __wasi_fd_t,
+        path_ptr: *const u8,
+        path_len: usize,
+        buf: *mut u8,
+        buf_len: __wasi_size_t,
+        bufused: *mut __wasi_size_t,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_path_readlink(fd, path_ptr, path_len, buf, buf_len, bufused);
+        if r == __WASI_ESUCCESS {
+            filled(
+                Func::PathReadlink,
+                r,
+                core::slice::from_raw_parts(buf, *bufused),
+            )
+        } else {
+            done(Func::PathReadlink, r)
+        }
+    }
+
+    pub unsafe fn __wasi_path_remove_directory(
+        fd: __wasi_fd_t,
+        path_ptr: *const u8,
+        path_len: usize,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_path_remove_directory(fd, path_ptr, path_len);
+        done(Func::PathRemoveDirectory, r)
+    }
+
+    pub unsafe fn __wasi_path_rename(
+        fd: __wasi_fd_t,
+        old_path_ptr: *const u8,
+        old_path_len: usize,
+        new_fd: __wasi_fd_t,
+        new_path_ptr: *const u8,
+        new_path_len: usize,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_path_rename(
+            fd,
+            old_path_ptr,
+            old_path_len,
+            new_fd,
+            new_path_ptr,
+            new_path_len,
+        );
+        done(Func::PathRename, r)
+    }
+
+    pub unsafe fn __wasi_path_symlink(
+        old_path_ptr: *const u8,
+        old_path_len: usize,
+        fd: __wasi_fd_t,
+        new_path_ptr: *const u8,
+        new_path_len: usize,
+    ) -> __wasi_errno_t {
+        let r =
+            raw::__wasi_path_symlink(old_path_ptr, old_path_len, fd, new_path_ptr, new_path_len);
+        done(Func::PathSymlink, r)
+    }
+
+    pub unsafe fn __wasi_path_unlink_file(
+        fd: __wasi_fd_t,
+        path_ptr: *const u8,
+        path_len: usize,
+    ) -> __wasi_errno_t {
+        done(
+            Func::PathUnlinkFile,
+            raw::__wasi_path_unlink_file(fd, path_ptr, path_len),
+        )
+    }
+
+    pub unsafe fn __wasi_poll_oneoff(
+        r#in: *const __wasi_subscription_t,
+        out: *mut __wasi_event_t,
+        nsubscriptions: __wasi_size_t,
+        nevents: *mut __wasi_size_t,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_poll_oneoff(r#in, out, nsubscriptions, nevents);
+        if r != __WASI_ESUCCESS {
+            return done(Func::PollOneoff, r);
+        }
+        let events = core::slice::from_raw_parts(out, *nevents);
+        let len = 8 + events.len() * EVENT_LEN;
+        if let Some(mut w) = EntryWriter::begin(Func::PollOneoff, r, len) {
+            w.write(&(events.len() as u64).to_le_bytes());
+            for event in events {
+                let mut buf = [0; EVENT_LEN];
+                Encoder::new(&mut buf).event(event);
+                w.write(&buf);
+            }
+        }
+        r
+    }
+
+    pub unsafe fn __wasi_proc_exit(rval: __wasi_exitcode_t) -> ! {
+        emit(Func::ProcExit, __WASI_ESUCCESS, &[&rval.to_le_bytes()]);
+        raw::__wasi_proc_exit(rval)
+    }
+
+    pub unsafe fn __wasi_proc_raise(sig: __wasi_signal_t) -> __wasi_errno_t {
+        done(Func::ProcRaise, raw::__wasi_proc_raise(sig))
+    }
+
+    pub unsafe fn __wasi_sched_yield() -> __wasi_errno_t {
+        done(Func::SchedYield, raw::__wasi_sched_yield())
+    }
+
+    pub unsafe fn __wasi_random_get(buf: *mut u8, buf_len: __wasi_size_t) -> __wasi_errno_t {
+        let r = raw::__wasi_random_get(buf, buf_len);
+        filled(
+            Func::RandomGet,
+            r,
+            core::slice::from_raw_parts(buf, buf_len),
+        )
+    }
+
+    pub unsafe fn __wasi_sock_recv(
+        fd: __wasi_fd_t,
+        ri_data_ptr: *const __wasi_iovec_t,
+        ri_data_len: usize,
+        ri_flags: __wasi_riflags_t,
+        ro_datalen: *mut __wasi_size_t,
+        ro_flags: *mut __wasi_roflags_t,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_sock_recv(fd, ri_data_ptr, ri_data_len, ri_flags, ro_datalen, ro_flags);
+        if r == __WASI_ESUCCESS {
+            let flags = (*ro_flags).to_le_bytes();
+            emit_iovs(
+                Func::SockRecv,
+                &flags,
+                ri_data_ptr,
+                ri_data_len,
+                *ro_datalen,
+            );
+            r
+        } else {
+            done(Func::SockRecv, r)
+        }
+    }
+
+    pub unsafe fn __wasi_sock_send(
+        fd: __wasi_fd_t,
+        si_data_ptr: *const __wasi_ciovec_t,
+        si_data_len: usize,
+        si_flags: __wasi_siflags_t,
+        so_datalen: *mut __wasi_size_t,
+    ) -> __wasi_errno_t {
+        let r = raw::__wasi_sock_send(fd, si_data_ptr, si_data_len, si_flags, so_datalen);
+        value(Func::SockSend, r, || *so_datalen as u64)
+    }
+
+    pub unsafe fn __wasi_sock_shutdown(fd: __wasi_fd_t, how: __wasi_sdflags_t) -> __wasi_errno_t {
+        done(Func::SockShutdown, raw::__wasi_sock_shutdown(fd, how))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    /// Appends an entry for `func` to `log`.
+    pub(crate) fn entry(log: &mut Vec<u8>, func: Func, errno: __wasi_errno_t, payload: &[u8]) {
+        log.push(func as u8);
+        log.extend_from_slice(&errno.to_le_bytes());
+        log.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        log.extend_from_slice(payload);
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        let mut log = Vec::new();
+        entry(&mut log, Func::FdTell, __WASI_ESUCCESS, &7u64.to_le_bytes());
+        entry(&mut log, Func::FdClose, __WASI_EBADF, &[]);
+        let mut iter = entries(&log);
+        let first = iter.next().unwrap();
+        assert_eq!(first.func, Func::FdTell);
+        assert_eq!(first.errno, __WASI_ESUCCESS);
+        assert_eq!(first.payload, &7u64.to_le_bytes());
+        let second = iter.next().unwrap();
+        assert_eq!(second.func, Func::FdClose);
+        assert_eq!(second.errno, __WASI_EBADF);
+        assert!(second.payload.is_empty());
+        assert!(iter.next().is_none());
+        assert!(iter.remainder().is_empty());
+    }
+
+    #[test]
+    fn truncated_entry_ends_iteration() {
+        let mut log = Vec::new();
+        entry(&mut log, Func::FdTell, __WASI_ESUCCESS, &7u64.to_le_bytes());
+        entry(&mut log, Func::FdTell, __WASI_ESUCCESS, &8u64.to_le_bytes());
+        log.truncate(log.len() - 3);
+        let mut iter = entries(&log);
+        assert!(iter.next().is_some());
+        assert!(iter.next().is_none());
+        assert_eq!(iter.remainder().len(), HEADER_LEN + 5);
+
+        // A header on its own is truncated too.
+        assert!(entries(&log[..HEADER_LEN - 1]).next().is_none());
+    }
+
+    #[test]
+    fn oversized_length_ends_iteration() {
+        let mut log = Vec::new();
+        entry(&mut log, Func::RandomGet, __WASI_ESUCCESS, &[1; 4]);
+        log[3..7].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut iter = entries(&log);
+        assert!(iter.next().is_none());
+        assert_eq!(iter.remainder(), &log[..]);
+    }
+
+    #[test]
+    fn unknown_func_ends_iteration() {
+        let mut log = Vec::new();
+        entry(&mut log, Func::FdClose, __WASI_ESUCCESS, &[]);
+        log[0] = u8::MAX;
+        assert!(entries(&log).next().is_none());
+    }
+}
@@ -0,0 +1,727 @@
+//! Replaying of a log recorded with the `record` feature.
+//!
+//! With the `replay` feature enabled, the wrappers in the parent module call
+//! shims instead of the `wasi_unstable` imports. Each shim takes the next
+//! entry of the log passed to [`load`] and hands its results back to the
+//! caller, without involving a host at all. This makes it possible to run a
+//! guest natively, e.g. under a debugger or in a unit test, and have it see
+//! exactly the clock readings, random data, directory listings and file
+//! contents it saw when the log was recorded.
+//!
+//! The guest is expected to make the same calls in the same order as when
+//! it was recorded. If it makes a different call, or runs past the end of
+//! the log, the shim panics with a description of where replay diverged.
+//! `proc_exit` panics as well, since there is no host to exit to.
+//!
+//! Replay state is global, so only one guest can be replayed at a time.
+
+use super::raw::*;
+use super::record::{entries, Decoder, Entry, Func};
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+static LOG: AtomicPtr<u8> = AtomicPtr::new(core::ptr::null_mut());
+static LOG_LEN: AtomicUsize = AtomicUsize::new(0);
+static POS: AtomicUsize = AtomicUsize::new(0);
+
+/// The sizes last replayed by `args_sizes_get` or `environ_sizes_get`,
+/// which are those of the buffers passed to `args_get` or `environ_get`.
+struct Sizes {
+    count: AtomicUsize,
+    buf_len: AtomicUsize,
+}
+
+static ARGS_SIZES: Sizes = Sizes::new();
+static ENVIRON_SIZES: Sizes = Sizes::new();
+
+impl Sizes {
+    const fn new() -> Self {
+        Sizes {
+            count: AtomicUsize::new(0),
+            buf_len: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// Starts replaying `log` from its first entry.
+pub fn load(log: &'static [u8]) {
+    LOG.store(log.as_ptr() as *mut u8, Ordering::SeqCst);
+    LOG_LEN.store(log.len(), Ordering::SeqCst);
+    POS.store(0, Ordering::SeqCst);
+}
+
+fn log() -> &'static [u8] {
+    let ptr = LOG.load(Ordering::SeqCst);
+    if ptr.is_null() {
+        return &[];
+    }
+    unsafe { core::slice::from_raw_parts(ptr, LOG_LEN.load(Ordering::SeqCst)) }
+}
+
+/// Returns the part of the log which hasn't been replayed yet.
+///
+/// This is empty once the guest has made every call that was recorded.
+pub fn remaining() -> &'static [u8] {
+    &log()[POS.load(Ordering::SeqCst)..]
+}
+
+/// Takes the next entry of the log, which has to be for `func`.
+fn next(func: Func) -> Entry<'static> {
+    let rest = remaining();
+    let mut iter = entries(rest);
+    let entry = match iter.next() {
+        Some(entry) => entry,
+        None if rest.is_empty() => panic!("replay: log exhausted at call to {}", func),
+        None => panic!("replay: malformed log entry at call to {}", func),
+    };
+    if entry.func != func {
+        panic!(
+            "replay: guest called {} but {} was recorded at this point",
+            func, entry.func
+        );
+    }
+    POS.fetch_add(rest.len() - iter.remainder().len(), Ordering::SeqCst);
+    entry
+}
+
+/// Takes the next entry, returning its payload if the call succeeded and
+/// its errno otherwise.
+fn payload(func: Func) -> Result<Decoder<'static>, __wasi_errno_t> {
+    let entry = next(func);
+    if entry.errno == __WASI_ESUCCESS {
+        Ok(Decoder::new(func, entry.payload))
+    } else {
+        Err(entry.errno)
+    }
+}
+
+/// Panics unless `len` items of what was recorded for `func` fit in the
+/// `capacity` the guest passed, since the guest must have made a different
+/// call than when it was recorded.
+fn check_fits(func: Func, len: usize, capacity: usize) {
+    if len > capacity {
+        panic!(
+            "replay: guest called {} with room for {} but {} were recorded at this point",
+            func, capacity, len
+        );
+    }
+}
+
+/// Copies `data`, recorded for `func`, into the buffers of `iovs` in order.
+unsafe fn scatter(func: Func, data: &[u8], iovs: *const __wasi_iovec_t, iovs_len: usize) {
+    let iovs = core::slice::from_raw_parts(iovs, iovs_len);
+    let capacity = iovs
+        .iter()
+        .fold(0usize, |sum, iov| sum.saturating_add(iov.buf_len));
+    check_fits(func, data.len(), capacity);
+    let mut rest = data;
+    for iov in iovs {
+        let n = iov.buf_len.min(rest.len());
+        core::ptr::copy_nonoverlapping(rest.as_ptr(), iov.buf, n);
+        rest = &rest[n..];
+    }
+}
+
+/// The shims the parent module calls instead of the raw imports.
+pub(crate) mod imports {
+    pub use super::super::raw::*;
+    use super::*;
+
+    fn done(func: Func) -> __wasi_errno_t {
+        next(func).errno
+    }
+
+    unsafe fn value<T>(func: Func, out: *mut T, convert: impl FnOnce(u64) -> T) -> __wasi_errno_t {
+        match payload(func) {
+            Ok(mut d) => {
+                *out = convert(d.u64());
+                __WASI_ESUCCESS
+            }
+            Err(errno) => errno,
+        }
+    }
+
+    /// Copies the payload of the next entry into `buf`, returning its length.
+    unsafe fn filled(func: Func, buf: *mut u8, buf_len: usize) -> Result<usize, __wasi_errno_t> {
+        let data = payload(func)?.rest();
+        check_fits(func, data.len(), buf_len);
+        core::ptr::copy_nonoverlapping(data.as_ptr(), buf, data.len());
+        Ok(data.len())
+    }
+
+    fn status(r: Result<(), __wasi_errno_t>) -> __wasi_errno_t {
+        match r {
+            Ok(()) => __WASI_ESUCCESS,
+            Err(errno) => errno,
+        }
+    }
+
+    // Only `args_get` and `environ_get` use this, which need `alloc`.
+    #[cfg_attr(not(feature = "alloc"), allow(dead_code))]
+    unsafe fn strings(
+        func: Func,
+        sizes: &Sizes,
+        ptrs: *mut *mut u8,
+        buf: *mut u8,
+    ) -> __wasi_errno_t {
+        let len = match filled(func, buf, sizes.buf_len.load(Ordering::SeqCst)) {
+            Ok(len) => len,
+            Err(errno) => return errno,
+        };
+        let count = core::slice::from_raw_parts(buf, len)
+            .iter()
+            .filter(|&&c| c == 0)
+            .count();
+        check_fits(func, count, sizes.count.load(Ordering::SeqCst));
+        // Point each entry at the start of its NUL-terminated string.
+        let mut start = 0;
+        let mut i = 0;
+        for n in 0..len {
+            if *buf.add(n) == 0 {
+                *ptrs.add(i) = buf.add(start);
+                i += 1;
+                start = n + 1;
+            }
+        }
+        __WASI_ESUCCESS
+    }
+
+    unsafe fn sizes(func: Func, sizes: &Sizes, a: *mut usize, b: *mut usize) -> __wasi_errno_t {
+        match payload(func) {
+            Ok(mut d) => {
+                *a = d.u64() as usize;
+                *b = d.u64() as usize;
+                sizes.count.store(*a, Ordering::SeqCst);
+                sizes.buf_len.store(*b, Ordering::SeqCst);
+                __WASI_ESUCCESS
+            }
+            Err(errno) => errno,
+        }
+    }
+
+    pub unsafe fn __wasi_args_get(argv: *mut *mut u8, argv_buf: *mut u8) -> __wasi_errno_t {
+        strings(Func::ArgsGet, &ARGS_SIZES, argv, argv_buf)
+    }
+
+    pub unsafe fn __wasi_args_sizes_get(
+        argc: *mut usize,
+        argv_buf_size: *mut usize,
+    ) -> __wasi_errno_t {
+        sizes(Func::ArgsSizesGet, &ARGS_SIZES, argc, argv_buf_size)
+    }
+
+    pub unsafe fn __wasi_environ_get(
+        environ: *mut *mut u8,
+        environ_buf: *mut u8,
+    ) -> __wasi_errno_t {
+        strings(Func::EnvironGet, &ENVIRON_SIZES, environ, environ_buf)
+    }
+
+    pub unsafe fn __wasi_environ_sizes_get(
+        argc: *mut usize,
+        argv_buf_size: *mut usize,
+    ) -> __wasi_errno_t {
+        sizes(Func::EnvironSizesGet, &ENVIRON_SIZES, argc, argv_buf_size)
+    }
+
+    pub unsafe fn __wasi_clock_res_get(
+        _clock_id: __wasi_clockid_t,
+        resolution: *mut __wasi_timestamp_t,
+    ) -> __wasi_errno_t {
+        value(Func::ClockResGet, resolution, |v| v)
+    }
+
+    pub unsafe fn __wasi_clock_time_get(
+        _clock_id: __wasi_clockid_t,
+        _precision: __wasi_timestamp_t,
+        time: *mut __wasi_timestamp_t,
+    ) -> __wasi_errno_t {
+        value(Func::ClockTimeGet, time, |v| v)
+    }
+
+    pub unsafe fn __wasi_fd_advise(
+        _fd: __wasi_fd_t,
+        _offset: __wasi_filesize_t,
+        _len: __wasi_filesize_t,
+        _advice: __wasi_advice_t,
+    ) -> __wasi_errno_t {
+        done(Func::FdAdvise)
+    }
+
+    pub unsafe fn __wasi_fd_allocate(
+        _fd: __wasi_fd_t,
+        _offset: __wasi_filesize_t,
+        _len: __wasi_filesize_t,
+    ) -> __wasi_errno_t {
+        done(Func::FdAllocate)
+    }
+
+    pub unsafe fn __wasi_fd_close(_fd: __wasi_fd_t) -> __wasi_errno_t {
+        done(Func::FdClose)
+    }
+
+    pub unsafe fn __wasi_fd_datasync(_fd: __wasi_fd_t) -> __wasi_errno_t {
+        done(Func::FdDatasync)
+    }
+
+    pub unsafe fn __wasi_fd_fdstat_get(
+        _fd: __wasi_fd_t,
+        stat: *mut __wasi_fdstat_t,
+    ) -> __wasi_errno_t {
+        match payload(Func::FdFdstatGet) {
+            Ok(mut d) => {
+                *stat = d.fdstat();
+                __WASI_ESUCCESS
+            }
+            Err(errno) => errno,
+        }
+    }
+
+    pub unsafe fn __wasi_fd_fdstat_set_flags(
+        _fd: __wasi_fd_t,
+        _flags: __wasi_fdflags_t,
+    ) -> __wasi_errno_t {
+        done(Func::FdFdstatSetFlags)
+    }
+
+    pub unsafe fn __wasi_fd_fdstat_set_rights(
+        _fd: __wasi_fd_t,
+        _fs_rights_base: __wasi_rights_t,
+        _fs_rights_inheriting: __wasi_rights_t,
+    ) -> __wasi_errno_t {
+        done(Func::FdFdstatSetRights)
+    }
+
+    pub unsafe fn __wasi_fd_filestat_get(
+        _fd: __wasi_fd_t,
+        buf: *mut __wasi_filestat_t,
+    ) -> __wasi_errno_t {
+        match payload(Func::FdFilestatGet) {
+            Ok(mut d) => {
+                *buf = d.filestat();
+                __WASI_ESUCCESS
+            }
+            Err(errno) => errno,
+        }
+    }
+
+    pub unsafe fn __wasi_fd_filestat_set_size(
+        _fd: __wasi_fd_t,
+        _st_size: __wasi_filesize_t,
+    ) -> __wasi_errno_t {
+        done(Func::FdFilestatSetSize)
+    }
+
+    pub unsafe fn __wasi_fd_filestat_set_times(
+        _fd: __wasi_fd_t,
+        _st_atim: __wasi_timestamp_t,
+        _st_mtim: __wasi_timestamp_t,
+        _fst_flags: __wasi_fstflags_t,
+    ) -> __wasi_errno_t {
+        done(Func::FdFilestatSetTimes)
+    }
+
+    pub unsafe fn __wasi_fd_pread(
+        _fd: __wasi_fd_t,
+        iovs_ptr: *const __wasi_iovec_t,
+        iovs_len: usize,
+        _offset: __wasi_filesize_t,
+        nread: *mut __wasi_size_t,
+    ) -> __wasi_errno_t {
+        match payload(Func::FdPread) {
+            Ok(mut d) => {
+                let data = d.rest();
+                scatter(Func::FdPread, data, iovs_ptr, iovs_len);
+                *nread = data.len();
+                __WASI_ESUCCESS
+            }
+            Err(errno) => errno,
+        }
+    }
+
+    pub unsafe fn __wasi_fd_prestat_get(
+        _fd: __wasi_fd_t,
+        buf: *mut __wasi_prestat_t,
+    ) -> __wasi_errno_t {
+        match payload(Func::FdPrestatGet) {
+            Ok(mut d) => {
+                *buf = d.prestat();
+                __WASI_ESUCCESS
+            }
+            Err(errno) => errno,
+        }
+    }
+
+    pub unsafe fn __wasi_fd_prestat_dir_name(
+        _fd: __wasi_fd_t,
+        path: *mut u8,
+        path_len: __wasi_size_t,
+    ) -> __wasi_errno_t {
+        status(filled(Func::FdPrestatDirName, path, path_len).map(drop))
+    }
+
+    pub unsafe fn __wasi_fd_pwrite(
+        _fd: __wasi_fd_t,
+        _iovs_ptr: *const __wasi_ciovec_t,
+        _iovs_len: usize,
+        _offset: __wasi_filesize_t,
+        nwritten: *mut __wasi_size_t,
+    ) -> __wasi_errno_t {
+        value(Func::FdPwrite, nwritten, |v| v as usize)
+    }
+
+    pub unsafe fn __wasi_fd_read(
+        _fd: __wasi_fd_t,
+        iovs_ptr: *const __wasi_iovec_t,
+        iovs_len: usize,
+        nread: *mut __wasi_size_t,
+    ) -> __wasi_errno_t {
+        match payload(Func::FdRead) {
+            Ok(mut d) => {
+                let data = d.rest();
+                scatter(Func::FdRead, data, iovs_ptr, iovs_len);
+                *nread = data.len();
+                __WASI_ESUCCESS
+            }
+            Err(errno) => errno,
+        }
+    }
+
+    pub unsafe fn __wasi_fd_readdir(
+        _fd: __wasi_fd_t,
+        buf: *mut u8,
+        buf_len: __wasi_size_t,
+        _cookie: __wasi_dircookie_t,
+        bufused: *mut __wasi_size_t,
+    ) -> __wasi_errno_t {
+        status(filled(Func::FdReaddir, buf, buf_len).map(|n| *bufused = n))
+    }
+
+    pub unsafe fn __wasi_fd_renumber(_fd: __wasi_fd_t, _to: __wasi_fd_t) -> __wasi_errno_t {
+        done(Func::FdRenumber)
+    }
+
+    pub unsafe fn __wasi_fd_seek(
+        _fd: __wasi_fd_t,
+        _offset: __wasi_filedelta_t,
+        _whence: __wasi_whence_t,
+        newoffset: *mut __wasi_filesize_t,
+    ) -> __wasi_errno_t {
+        value(Func::FdSeek, newoffset, |v| v)
+    }
+
+    pub unsafe fn __wasi_fd_sync(_fd: __wasi_fd_t) -> __wasi_errno_t {
+        done(Func::FdSync)
+    }
+
+    pub unsafe fn __wasi_fd_tell(
+        _fd: __wasi_fd_t,
+        offset: *mut __wasi_filesize_t,
+    ) -> __wasi_errno_t {
+        value(Func::FdTell, offset, |v| v)
+    }
+
+    pub unsafe fn __wasi_fd_write(
+        _fd: __wasi_fd_t,
+        _iovs_ptr: *const __wasi_ciovec_t,
+        _iovs_len: usize,
+        nwritten: *mut __wasi_size_t,
+    ) -> __wasi_errno_t {
+        value(Func::FdWrite, nwritten, |v| v as usize)
+    }
+
+    pub unsafe fn __wasi_path_create_directory(
+        _fd: __wasi_fd_t,
+        _path_ptr: *const u8,
+        _path_len: usize,
+    ) -> __wasi_errno_t {
+        done(Func::PathCreateDirectory)
+    }
+
+    pub unsafe fn __wasi_path_filestat_get(
+        _fd: __wasi_fd_t,
+        _flags: __wasi_lookupflags_t,
+        _path_ptr: *const u8,
+        _path_len: usize,
+        buf: *mut __wasi_filestat_t,
+    ) -> __wasi_errno_t {
+        match payload(Func::PathFilestatGet) {
+            Ok(mut d) => {
+                *buf = d.filestat();
+                __WASI_ESUCCESS
+            }
+            Err(errno) => errno,
+        }
+    }
+
+    pub unsafe fn __wasi_path_filestat_set_times(
+        _fd: __wasi_fd_t,
+        _flags: __wasi_lookupflags_t,
+        _path_ptr: *const u8,
+        _path_len: usize,
+        _st_atim: __wasi_timestamp_t,
+        _st_mtim: __wasi_timestamp_t,
+        _fst_flags: __wasi_fstflags_t,
+    ) -> __wasi_errno_t {
+        done(Func::PathFilestatSetTimes)
+    }
+
+    pub unsafe fn __wasi_path_link(
+        _old_fd: __wasi_fd_t,
+        _old_flags: __wasi_lookupflags_t,
+        _old_path_ptr: *const u8,
+        _old_path_len: usize,
+        _new_fd: __wasi_fd_t,
+        _new_path_ptr: *const u8,
+        _new_path_len: usize,
+    ) -> __wasi_errno_t {
+        done(Func::PathLink)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn __wasi_path_open(
+        _fd: __wasi_fd_t,
+        _dirflags: __wasi_lookupflags_t,
+        _path_ptr: *const u8,
+        _path_len: usize,
+        _o_flags: __wasi_oflags_t,
+        _fs_rights_base: __wasi_rights_t,
+        _fs_rights_inherting: __wasi_rights_t,
+        _flags: __wasi_fdflags_t,
+        opened_fd: *mut __wasi_fd_t,
+    ) -> __wasi_errno_t {
+        value(Func::PathOpen, opened_fd, |v| v as __wasi_fd_t)
+    }
+
+    pub unsafe fn __wasi_path_readlink(
+        _fd: __wasi_fd_t,
+        _path_ptr: *const u8,
+        _path_len: usize,
+        buf: *mut u8,
+        buf_len: __wasi_size_t,
+        bufused: *mut __wasi_size_t,
+    ) -> __wasi_errno_t {
+        status(filled(Func::PathReadlink, buf, buf_len).map(|n| *bufused = n))
+    }
+
+    pub unsafe fn __wasi_path_remove_directory(
+        _fd: __wasi_fd_t,
+        _path_ptr: *const u8,
+        _path_len: usize,
+    ) -> __wasi_errno_t {
+        done(Func::PathRemoveDirectory)
+    }
+
+    pub unsafe fn __wasi_path_rename(
+        _fd: __wasi_fd_t,
+        _old_path_ptr: *const u8,
+        _old_path_len: usize,
+        _new_fd: __wasi_fd_t,
+        _new_path_ptr: *const u8,
+        _new_path_len: usize,
+    ) -> __wasi_errno_t {
+        done(Func::PathRename)
+    }
+
+    pub unsafe fn __wasi_path_symlink(
+        _old_path_ptr: *const u8,
+        _old_path_len: usize,
+        _fd: __wasi_fd_t,
+        _new_path_ptr: *const u8,
+        _new_path_len: usize,
+    ) -> __wasi_errno_t {
+        done(Func::PathSymlink)
+    }
+
+    pub unsafe fn __wasi_path_unlink_file(
+        _fd: __wasi_fd_t,
+        _path_ptr: *const u8,
+        _path_len: usize,
+    ) -> __wasi_errno_t {
+        done(Func::PathUnlinkFile)
+    }
+
+    pub unsafe fn __wasi_poll_oneoff(
+        _in: *const __wasi_subscription_t,
+        out: *mut __wasi_event_t,
+        nsubscriptions: __wasi_size_t,
+        nevents: *mut __wasi_size_t,
+    ) -> __wasi_errno_t {
+        match payload(Func::PollOneoff) {
+            Ok(mut d) => {
+                let n = d.u64() as usize;
+                // Each subscription yields at most one event.
+                check_fits(Func::PollOneoff, n, nsubscriptions);
+                for i in 0..n {
+                    *out.add(i) = d.event();
+                }
+                *nevents = n;
+                __WASI_ESUCCESS
+            }
+            Err(errno) => errno,
+        }
+    }
+
+    pub unsafe fn __wasi_proc_exit(rval: __wasi_exitcode_t) -> ! {
+        next(Func::ProcExit);
+        panic!("replay: guest exited with code {}", rval)
+    }
+
+    pub unsafe fn __wasi_proc_raise(_sig: __wasi_signal_t) -> __wasi_errno_t {
+        done(Func::ProcRaise)
+    }
+
+    pub unsafe fn __wasi_sched_yield() -> __wasi_errno_t {
+        done(Func::SchedYield)
+    }
+
+    pub unsafe fn __wasi_random_get(buf: *mut u8, buf_len: __wasi_size_t) -> __wasi_errno_t {
+        status(filled(Func::RandomGet, buf, buf_len).map(drop))
+    }
+
+    pub unsafe fn __wasi_sock_recv(
+        _fd: __wasi_fd_t,
+        ri_data_ptr: *const __wasi_iovec_t,
+        ri_data_len: usize,
+        _ri_flags: __wasi_riflags_t,
+        ro_datalen: *mut __wasi_size_t,
+        ro_flags: *mut __wasi_roflags_t,
+    ) -> __wasi_errno_t {
+        match payload(Func::SockRecv) {
+            Ok(mut d) => {
+                *ro_flags = d.u16();
+                let data = d.rest();
+                scatter(Func::SockRecv, data, ri_data_ptr, ri_data_len);
+                *ro_datalen = data.len();
+                __WASI_ESUCCESS
+            }
+            Err(errno) => errno,
+        }
+    }
+
+    pub unsafe fn __wasi_sock_send(
+        _fd: __wasi_fd_t,
+        _si_data_ptr: *const __wasi_ciovec_t,
+        _si_data_len: usize,
+        _si_flags: __wasi_siflags_t,
+        so_datalen: *mut __wasi_size_t,
+    ) -> __wasi_errno_t {
+        value(Func::SockSend, so_datalen, |v| v as usize)
+    }
+
+    pub unsafe fn __wasi_sock_shutdown(_fd: __wasi_fd_t, _how: __wasi_sdflags_t) -> __wasi_errno_t {
+        done(Func::SockShutdown)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::record::tests::entry;
+    use super::super::record::Func;
+    use super::imports::*;
+    use super::{load, remaining};
+    use std::boxed::Box;
+    use std::sync::{Mutex, MutexGuard};
+    use std::vec::Vec;
+
+    /// Replay state is global, so tests replaying a log take turns.
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    fn replay(log: Vec<u8>) -> MutexGuard<'static, ()> {
+        // A test that panicked on purpose poisons the lock.
+        let guard = LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        load(Box::leak(log.into_boxed_slice()));
+        guard
+    }
+
+    #[test]
+    fn replays_values_and_errors() {
+        let mut log = Vec::new();
+        entry(
+            &mut log,
+            Func::FdTell,
+            __WASI_ESUCCESS,
+            &42u64.to_le_bytes(),
+        );
+        entry(&mut log, Func::FdTell, __WASI_EBADF, &[]);
+        let _guard = replay(log);
+        let mut offset = 0;
+        assert_eq!(unsafe { __wasi_fd_tell(3, &mut offset) }, __WASI_ESUCCESS);
+        assert_eq!(offset, 42);
+        assert_eq!(unsafe { __wasi_fd_tell(3, &mut offset) }, __WASI_EBADF);
+        assert!(remaining().is_empty());
+    }
+
+    #[test]
+    fn replays_reads_across_iovecs() {
+        let mut log = Vec::new();
+        entry(&mut log, Func::FdRead, __WASI_ESUCCESS, b"hello");
+        let _guard = replay(log);
+        let (mut a, mut b) = ([0; 2], [0; 4]);
+        let iovs = [
+            __wasi_iovec_t {
+                buf: a.as_mut_ptr(),
+                buf_len: a.len(),
+            },
+            __wasi_iovec_t {
+                buf: b.as_mut_ptr(),
+                buf_len: b.len(),
+            },
+        ];
+        let mut nread = 0;
+        let r = unsafe { __wasi_fd_read(3, iovs.as_ptr(), iovs.len(), &mut nread) };
+        assert_eq!(r, __WASI_ESUCCESS);
+        assert_eq!(nread, 5);
+        assert_eq!((&a, &b), (b"he", b"llo\0"));
+    }
+
+    #[test]
+    #[should_panic(expected = "guest called random_get with room for 8 but 32 were recorded")]
+    fn payload_larger_than_buffer_panics() {
+        let mut log = Vec::new();
+        entry(&mut log, Func::RandomGet, __WASI_ESUCCESS, &[1; 32]);
+        let _guard = replay(log);
+        let mut buf = [0; 8];
+        unsafe { __wasi_random_get(buf.as_mut_ptr(), buf.len()) };
+    }
+
+    #[test]
+    #[should_panic(expected = "guest called fd_read with room for 4 but 5 were recorded")]
+    fn payload_larger_than_iovecs_panics() {
+        let mut log = Vec::new();
+        entry(&mut log, Func::FdRead, __WASI_ESUCCESS, b"hello");
+        let _guard = replay(log);
+        let mut buf = [0; 4];
+        let iov = __wasi_iovec_t {
+            buf: buf.as_mut_ptr(),
+            buf_len: buf.len(),
+        };
+        let mut nread = 0;
+        unsafe { __wasi_fd_read(3, &iov, 1, &mut nread) };
+    }
+
+    #[test]
+    #[should_panic(expected = "fd_tell needs 8 more bytes but only 3 were recorded")]
+    fn short_payload_panics() {
+        let mut log = Vec::new();
+        entry(&mut log, Func::FdTell, __WASI_ESUCCESS, &[1; 3]);
+        let _guard = replay(log);
+        let mut offset = 0;
+        unsafe { __wasi_fd_tell(3, &mut offset) };
+    }
+
+    #[test]
+    #[should_panic(expected = "guest called fd_sync but fd_tell was recorded")]
+    fn different_call_panics() {
+        let mut log = Vec::new();
+        entry(&mut log, Func::FdTell, __WASI_ESUCCESS, &0u64.to_le_bytes());
+        let _guard = replay(log);
+        unsafe { __wasi_fd_sync(3) };
+    }
+
+    #[test]
+    #[should_panic(expected = "log exhausted at call to fd_sync")]
+    fn exhausted_log_panics() {
+        let _guard = replay(Vec::new());
+        unsafe { __wasi_fd_sync(3) };
+    }
+}
@@ -0,0 +1,244 @@
+//! Inspecting and dropping the rights of a file descriptor.
+//!
+//! Every WASI file descriptor carries two sets of rights: the base rights,
+//! which limit what can be done with the descriptor itself, and the
+//! inheriting rights, which limit what descriptors opened through it may be
+//! given. [`FdRights`] decodes them, and [`restrict`] drops rights from a
+//! descriptor before it is handed to less trusted code. Rights can never be
+//! added back once dropped.
+
+use super::*;
+use core::fmt;
+
+/// The rights and their names, in bit order.
+pub(crate) const RIGHT_NAMES: &[(Rights, &str)] = &[
+    (RIGHT_FD_DATASYNC, "RIGHT_FD_DATASYNC"),
+    (RIGHT_FD_READ, "RIGHT_FD_READ"),
+    (RIGHT_FD_SEEK, "RIGHT_FD_SEEK"),
+    (RIGHT_FD_FDSTAT_SET_FLAGS, "RIGHT_FD_FDSTAT_SET_FLAGS"),
+    (RIGHT_FD_SYNC, "RIGHT_FD_SYNC"),
+    (RIGHT_FD_TELL, "RIGHT_FD_TELL"),
+    (RIGHT_FD_WRITE, "RIGHT_FD_WRITE"),
+    (RIGHT_FD_ADVISE, "RIGHT_FD_ADVISE"),
+    (RIGHT_FD_ALLOCATE, "RIGHT_FD_ALLOCATE"),
+    (RIGHT_PATH_CREATE_DIRECTORY, "RIGHT_PATH_CREATE_DIRECTORY"),
+    (RIGHT_PATH_CREATE_FILE, "RIGHT_PATH_CREATE_FILE"),
+    (RIGHT_PATH_LINK_SOURCE, "RIGHT_PATH_LINK_SOURCE"),
+    (RIGHT_PATH_LINK_TARGET, "RIGHT_PATH_LINK_TARGET"),
+    (RIGHT_PATH_OPEN, "RIGHT_PATH_OPEN"),
+    (RIGHT_FD_READDIR, "RIGHT_FD_READDIR"),
+    (RIGHT_PATH_READLINK, "RIGHT_PATH_READLINK"),
+    (RIGHT_PATH_RENAME_SOURCE, "RIGHT_PATH_RENAME_SOURCE"),
+    (RIGHT_PATH_RENAME_TARGET, "RIGHT_PATH_RENAME_TARGET"),
+    (RIGHT_PATH_FILESTAT_GET, "RIGHT_PATH_FILESTAT_GET"),
+    (RIGHT_PATH_FILESTAT_SET_SIZE, "RIGHT_PATH_FILESTAT_SET_SIZE"),
+    (
+        RIGHT_PATH_FILESTAT_SET_TIMES,
+        "RIGHT_PATH_FILESTAT_SET_TIMES",
+    ),
+    (RIGHT_FD_FILESTAT_GET, "RIGHT_FD_FILESTAT_GET"),
+    (RIGHT_FD_FILESTAT_SET_SIZE, "RIGHT_FD_FILESTAT_SET_SIZE"),
+    (RIGHT_FD_FILESTAT_SET_TIMES, "RIGHT_FD_FILESTAT_SET_TIMES"),
+    (RIGHT_PATH_SYMLINK, "RIGHT_PATH_SYMLINK"),
+    (RIGHT_PATH_REMOVE_DIRECTORY, "RIGHT_PATH_REMOVE_DIRECTORY"),
+    (RIGHT_PATH_UNLINK_FILE, "RIGHT_PATH_UNLINK_FILE"),
+    (RIGHT_POLL_FD_READWRITE, "RIGHT_POLL_FD_READWRITE"),
+    (RIGHT_SOCK_SHUTDOWN, "RIGHT_SOCK_SHUTDOWN"),
+];
+
+/// Returns the name of a single `RIGHT_*` constant, e.g. `"RIGHT_FD_READ"`.
+pub fn right_name(right: Rights) -> Option<&'static str> {
+    RIGHT_NAMES
+        .iter()
+        .find(|&&(r, _)| r == right)
+        .map(|&(_, name)| name)
+}
+
+/// Returns the names of the rights in `rights`, in bit order. Bits that
+/// aren't known rights are skipped.
+pub fn right_names(rights: Rights) -> impl Iterator<Item = &'static str> {
+    RIGHT_NAMES
+        .iter()
+        .filter(move |&&(r, _)| rights & r == r)
+        .map(|&(_, name)| name)
+}
+
+/// Formats a set of rights as its names joined with `|`, or `0`.
+struct Names(Rights);
+
+impl fmt::Debug for Names {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+        for name in right_names(self.0) {
+            if !first {
+                f.write_str("|")?;
+            }
+            f.write_str(name)?;
+            first = false;
+        }
+        let unknown = RIGHT_NAMES.iter().fold(self.0, |rest, &(r, _)| rest & !r);
+        if unknown != 0 {
+            if !first {
+                f.write_str("|")?;
+            }
+            write!(f, "{:#x}", unknown)?;
+        } else if first {
+            f.write_str("0")?;
+        }
+        Ok(())
+    }
+}
+
+/// The rights of a file descriptor.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FdRights {
+    base: Rights,
+    inheriting: Rights,
+}
+
+impl FdRights {
+    /// Creates a set of rights from its raw masks.
+    pub const fn new(base: Rights, inheriting: Rights) -> Self {
+        FdRights { base, inheriting }
+    }
+
+    /// Returns the rights of `fd`, as reported by `fd_fdstat_get`.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be a descriptor the caller is allowed to use.
+    pub unsafe fn of(fd: Fd) -> Result<Self, Error> {
+        let stat = fd_fdstat_get(fd)?;
+        Ok(FdRights::new(
+            stat.fs_rights_base,
+            stat.fs_rights_inheriting,
+        ))
+    }
+
+    /// Returns the rights that apply to the descriptor itself.
+    pub fn base(&self) -> Rights {
+        self.base
+    }
+
+    /// Returns the rights descriptors opened through this one may have.
+    pub fn inheriting(&self) -> Rights {
+        self.inheriting
+    }
+
+    /// Returns whether the base rights include all of `rights`.
+    pub fn contains(&self, rights: Rights) -> bool {
+        self.base & rights == rights
+    }
+
+    /// Returns whether `self` has no right that `other` lacks, in both
+    /// the base and the inheriting rights.
+    pub fn is_subset(&self, other: &FdRights) -> bool {
+        self.base & !other.base == 0 && self.inheriting & !other.inheriting == 0
+    }
+
+    /// Whether `fd_read` is allowed.
+    pub fn can_read(&self) -> bool {
+        self.contains(RIGHT_FD_READ)
+    }
+
+    /// Whether `fd_write` is allowed.
+    pub fn can_write(&self) -> bool {
+        self.contains(RIGHT_FD_WRITE)
+    }
+
+    /// Whether `fd_seek` is allowed. This also allows `fd_tell`.
+    pub fn can_seek(&self) -> bool {
+        self.contains(RIGHT_FD_SEEK)
+    }
+
+    /// Whether `fd_tell` is allowed.
+    pub fn can_tell(&self) -> bool {
+        self.contains(RIGHT_FD_TELL) || self.can_seek()
+    }
+
+    /// Whether `fd_sync` is allowed.
+    pub fn can_sync(&self) -> bool {
+        self.contains(RIGHT_FD_SYNC)
+    }
+
+    /// Whether `fd_readdir` is allowed.
+    pub fn can_read_dir(&self) -> bool {
+        self.contains(RIGHT_FD_READDIR)
+    }
+
+    /// Whether `path_open` is allowed relative to the descriptor.
+    pub fn can_open_paths(&self) -> bool {
+        self.contains(RIGHT_PATH_OPEN)
+    }
+
+    /// Whether `path_open` with `O_CREAT` is allowed relative to the
+    /// descriptor.
+    pub fn can_create_files(&self) -> bool {
+        self.contains(RIGHT_PATH_OPEN | RIGHT_PATH_CREATE_FILE)
+    }
+
+    /// Whether `path_create_directory` is allowed relative to the
+    /// descriptor.
+    pub fn can_create_dirs(&self) -> bool {
+        self.contains(RIGHT_PATH_CREATE_DIRECTORY)
+    }
+
+    /// Whether `path_unlink_file` and `path_remove_directory` are allowed
+    /// relative to the descriptor.
+    pub fn can_remove(&self) -> bool {
+        self.contains(RIGHT_PATH_UNLINK_FILE | RIGHT_PATH_REMOVE_DIRECTORY)
+    }
+
+    /// Whether `fd_filestat_get` is allowed.
+    pub fn can_stat(&self) -> bool {
+        self.contains(RIGHT_FD_FILESTAT_GET)
+    }
+
+    /// Whether the descriptor can be waited on with `poll_oneoff`.
+    pub fn can_poll(&self) -> bool {
+        self.contains(RIGHT_POLL_FD_READWRITE)
+    }
+}
+
+impl fmt::Debug for FdRights {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FdRights")
+            .field("base", &Names(self.base))
+            .field("inheriting", &Names(self.inheriting))
+            .finish()
+    }
+}
+
+/// Drops every right of `fd` not in `keep`, from both its base and its
+/// inheriting rights.
+///
+/// Fails with `ENOTCAPABLE`, leaving the rights unchanged, if `keep`
+/// includes a base right `fd` doesn't have: rights can only be dropped.
+///
+/// # Safety
+///
+/// `fd` must be owned by the caller, since every other user of the
+/// descriptor loses the dropped rights too.
+pub unsafe fn restrict(fd: Fd, keep: Rights) -> Result<(), Error> {
+    let current = FdRights::of(fd)?;
+    set_rights(fd, current, FdRights::new(keep, current.inheriting & keep))
+}
+
+/// Sets the rights of `fd` to `rights`.
+///
+/// Fails with `ENOTCAPABLE`, leaving the rights unchanged, if `rights`
+/// includes a right `fd` doesn't have.
+///
+/// # Safety
+///
+/// `fd` must be owned by the caller, as for [`restrict`].
+pub unsafe fn restrict_to(fd: Fd, rights: FdRights) -> Result<(), Error> {
+    set_rights(fd, FdRights::of(fd)?, rights)
+}
+
+unsafe fn set_rights(fd: Fd, current: FdRights, rights: FdRights) -> Result<(), Error> {
+    if !rights.is_subset(&current) {
+        return Err(ENOTCAPABLE);
+    }
+    fd_fdstat_set_rights(fd, rights.base, rights.inheriting)
+}
@@ -0,0 +1,166 @@
+//! `rand_core` random number generators backed by `random_get`.
+//!
+//! [`WasiRng`] asks the host for every value it produces. [`BufferedWasiRng`]
+//! fetches random bytes in blocks and hands out `next_u32`/`next_u64` values
+//! from the block, so generating many small values takes few host calls.
+//!
+//! The infallible `RngCore` methods panic if `random_get` fails; use
+//! `try_fill_bytes` to handle the error instead. Errors carry the WASI errno
+//! as a custom `rand_core::Error` code, see [`errno`].
+
+use super::*;
+use core::convert::TryFrom;
+use core::num::NonZeroU32;
+use rand_core::{CryptoRng, RngCore};
+
+/// Converts a WASI error into a `rand_core::Error`.
+///
+/// The errno is stored as a custom error code, offset from
+/// `rand_core::Error::CUSTOM_START`.
+fn to_rand_error(err: Error) -> rand_core::Error {
+    let code = rand_core::Error::CUSTOM_START + u32::from(err.get());
+    // `CUSTOM_START` is nonzero and adding a `u16` doesn't overflow.
+    rand_core::Error::from(NonZeroU32::new(code).unwrap())
+}
+
+/// Returns the WASI error that caused `err`, if it came from this module.
+pub fn errno(err: &rand_core::Error) -> Option<Error> {
+    let code = err.code()?.get();
+    let errno = code.checked_sub(rand_core::Error::CUSTOM_START)?;
+    Error::new(u16::try_from(errno).ok()?)
+}
+
+fn unwrap(result: Result<(), Error>) {
+    if let Err(err) = result {
+        match error_name(err) {
+            Some(name) => panic!("random_get failed: {}", name),
+            None => panic!("random_get failed: errno {}", err),
+        }
+    }
+}
+
+/// A random number generator that calls `random_get` for every request.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct WasiRng;
+
+impl RngCore for WasiRng {
+    fn next_u32(&mut self) -> u32 {
+        let mut buf = [0; 4];
+        unwrap(random_get(&mut buf));
+        u32::from_le_bytes(buf)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut buf = [0; 8];
+        unwrap(random_get(&mut buf));
+        u64::from_le_bytes(buf)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        unwrap(random_get(dest))
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        random_get(dest).map_err(to_rand_error)
+    }
+}
+
+impl CryptoRng for WasiRng {}
+
+const BLOCK_LEN: usize = 256;
+
+/// A random number generator that fetches bytes from `random_get` in blocks.
+///
+/// `next_u32`, `next_u64` and small `fill_bytes` requests are served from
+/// the current block, which is refilled once used up. Requests at least as
+/// large as a block go to the host directly. Bytes are cleared from the
+/// block as they are handed out.
+///
+/// A clone starts with an empty block, so it never returns the same bytes
+/// as the original.
+pub struct BufferedWasiRng {
+    block: [u8; BLOCK_LEN],
+    pos: usize,
+}
+
+impl BufferedWasiRng {
+    /// Creates a generator with an empty block; the first request fills it.
+    pub fn new() -> Self {
+        BufferedWasiRng {
+            block: [0; BLOCK_LEN],
+            pos: BLOCK_LEN,
+        }
+    }
+
+    /// Copies `dest.len()` bytes out of the block, refilling it as needed.
+    fn take(&mut self, mut dest: &mut [u8]) -> Result<(), Error> {
+        while !dest.is_empty() {
+            if self.pos == BLOCK_LEN {
+                random_get(&mut self.block)?;
+                self.pos = 0;
+            }
+            let n = core::cmp::min(dest.len(), BLOCK_LEN - self.pos);
+            let taken = &mut self.block[self.pos..self.pos + n];
+            dest[..n].copy_from_slice(taken);
+            for b in taken {
+                *b = 0;
+            }
+            self.pos += n;
+            dest = &mut dest[n..];
+        }
+        Ok(())
+    }
+
+    fn try_fill(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        if dest.len() >= BLOCK_LEN {
+            random_get(dest)
+        } else {
+            self.take(dest)
+        }
+    }
+}
+
+impl Clone for BufferedWasiRng {
+    fn clone(&self) -> Self {
+        BufferedWasiRng::new()
+    }
+}
+
+impl Default for BufferedWasiRng {
+    fn default() -> Self {
+        BufferedWasiRng::new()
+    }
+}
+
+impl core::fmt::Debug for BufferedWasiRng {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        // Don't print the buffered bytes.
+        f.debug_struct("BufferedWasiRng")
+            .field("available", &(BLOCK_LEN - self.pos))
+            .finish()
+    }
+}
+
+impl RngCore for BufferedWasiRng {
+    fn next_u32(&mut self) -> u32 {
+        let mut buf = [0; 4];
+        self.fill_bytes(&mut buf);
+        u32::from_le_bytes(buf)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut buf = [0; 8];
+        self.fill_bytes(&mut buf);
+        u64::from_le_bytes(buf)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        unwrap(self.try_fill(dest))
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.try_fill(dest).map_err(to_rand_error)
+    }
+}
+
+impl CryptoRng for BufferedWasiRng {}
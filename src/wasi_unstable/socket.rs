@@ -0,0 +1,203 @@
+//! Sockets handed to the program by the runtime.
+//!
+//! WASI has no way to create sockets; a runtime can pass pre-opened ones to
+//! the program. [`Socket`] owns such a descriptor, wraps `sock_recv`,
+//! `sock_send` and `sock_shutdown` with typed flags and results, and closes
+//! the descriptor when dropped. With the `std` feature it implements
+//! `std::io::Read` and `std::io::Write`.
+
+use super::*;
+use core::marker::PhantomData;
+
+/// A buffer to receive into, with the layout of an `IoVec`.
+#[repr(transparent)]
+pub struct IoSliceMut<'a> {
+    iov: IoVec,
+    marker: PhantomData<&'a mut [u8]>,
+}
+
+impl<'a> IoSliceMut<'a> {
+    /// Wraps `buf`.
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        IoSliceMut {
+            iov: IoVec {
+                buf: buf.as_mut_ptr(),
+                buf_len: buf.len(),
+            },
+            marker: PhantomData,
+        }
+    }
+}
+
+/// A buffer to send from, with the layout of a `CIoVec`.
+#[repr(transparent)]
+pub struct IoSlice<'a> {
+    iov: CIoVec,
+    marker: PhantomData<&'a [u8]>,
+}
+
+impl<'a> IoSlice<'a> {
+    /// Wraps `buf`.
+    pub fn new(buf: &'a [u8]) -> Self {
+        IoSlice {
+            iov: CIoVec {
+                buf: buf.as_ptr(),
+                buf_len: buf.len(),
+            },
+            marker: PhantomData,
+        }
+    }
+}
+
+/// The result of receiving from a socket.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Recv {
+    /// The number of bytes received.
+    pub len: usize,
+    /// Whether the message didn't fit in the buffers and was cut short.
+    pub truncated: bool,
+}
+
+impl Recv {
+    fn new((len, flags): (usize, RoFlags)) -> Self {
+        Recv {
+            len,
+            truncated: flags & SOCK_RECV_DATA_TRUNCATED != 0,
+        }
+    }
+}
+
+/// The directions [`Socket::shutdown`] shuts down.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Shutdown {
+    /// Shut down receiving, like `SHUT_RD`.
+    Read,
+    /// Shut down sending, like `SHUT_WR`.
+    Write,
+    /// Shut down both directions.
+    Both,
+}
+
+/// A socket, closed when dropped.
+#[derive(Debug)]
+pub struct Socket {
+    fd: Fd,
+}
+
+impl Socket {
+    /// Takes ownership of the socket `fd`.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open socket that nothing else closes.
+    pub unsafe fn from_raw_fd(fd: Fd) -> Self {
+        Socket { fd }
+    }
+
+    /// Returns the socket's file descriptor.
+    pub fn as_raw_fd(&self) -> Fd {
+        self.fd
+    }
+
+    /// Returns the socket's file descriptor, giving up ownership of it.
+    pub fn into_raw_fd(self) -> Fd {
+        let fd = self.fd;
+        core::mem::forget(self);
+        fd
+    }
+
+    fn recv_with(&self, bufs: &mut [IoSliceMut], flags: RiFlags) -> Result<Recv, Error> {
+        // `IoSliceMut` is a transparent wrapper around `IoVec`.
+        let iovs =
+            unsafe { core::slice::from_raw_parts(bufs.as_ptr() as *const IoVec, bufs.len()) };
+        unsafe { sock_recv(self.fd, iovs, flags) }.map(Recv::new)
+    }
+
+    /// Receives into `buf`.
+    pub fn recv(&self, buf: &mut [u8]) -> Result<Recv, Error> {
+        self.recv_with(&mut [IoSliceMut::new(buf)], 0)
+    }
+
+    /// Receives into `buf` without removing the data from the socket's
+    /// queue, so the next receive returns it again.
+    pub fn peek(&self, buf: &mut [u8]) -> Result<Recv, Error> {
+        self.recv_with(&mut [IoSliceMut::new(buf)], SOCK_RECV_PEEK)
+    }
+
+    /// Receives into `bufs`, filling them in order.
+    ///
+    /// With `waitall`, this waits until all the buffers are full, rather
+    /// than returning as soon as any data is available. It can still return
+    /// less on errors, disconnection or signals.
+    pub fn recv_vectored(&self, bufs: &mut [IoSliceMut], waitall: bool) -> Result<Recv, Error> {
+        let flags = if waitall { SOCK_RECV_WAITALL } else { 0 };
+        self.recv_with(bufs, flags)
+    }
+
+    /// Sends from `buf`, returning the number of bytes sent.
+    pub fn send(&self, buf: &[u8]) -> Result<usize, Error> {
+        self.send_vectored(&[IoSlice::new(buf)])
+    }
+
+    /// Sends from `bufs` in order, returning the number of bytes sent.
+    pub fn send_vectored(&self, bufs: &[IoSlice]) -> Result<usize, Error> {
+        // `IoSlice` is a transparent wrapper around `CIoVec`.
+        let iovs =
+            unsafe { core::slice::from_raw_parts(bufs.as_ptr() as *const CIoVec, bufs.len()) };
+        unsafe { sock_send(self.fd, iovs, 0) }
+    }
+
+    /// Shuts down receiving, sending or both.
+    pub fn shutdown(&self, how: Shutdown) -> Result<(), Error> {
+        let how = match how {
+            Shutdown::Read => SHUT_RD,
+            Shutdown::Write => SHUT_WR,
+            Shutdown::Both => SHUT_RD | SHUT_WR,
+        };
+        unsafe { sock_shutdown(self.fd, how) }
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        let _ = unsafe { fd_close(self.fd) };
+    }
+}
+
+#[cfg(feature = "std")]
+mod std_impls {
+    use super::*;
+    use std::io;
+
+    impl io::Read for &Socket {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.recv(buf).map(|r| r.len).map_err(io_error)
+        }
+    }
+
+    impl io::Write for &Socket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.send(buf).map_err(io_error)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl io::Read for Socket {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            (&*self).read(buf)
+        }
+    }
+
+    impl io::Write for Socket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            (&*self).write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
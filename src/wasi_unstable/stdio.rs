@@ -0,0 +1,190 @@
+//! Handles for the standard output and error streams.
+//!
+//! [`Stdout`] and [`Stderr`] implement `core::fmt::Write`, so they can be
+//! used with `write!` without needing `std` or an allocator. Writes go
+//! straight to `fd_write`; wrap a handle in a [`LineWriter`] to collect
+//! output into whole lines first. The `wasi_print!`, `wasi_println!`,
+//! `wasi_eprint!` and `wasi_eprintln!` macros are shorthands for the common
+//! case.
+
+use super::*;
+use core::fmt;
+
+/// Writes all of `buf` to `fd`, retrying on short writes.
+pub(crate) fn write_all(fd: Fd, mut buf: &[u8]) -> Result<(), Error> {
+    while !buf.is_empty() {
+        let iov = CIoVec {
+            buf: buf.as_ptr(),
+            buf_len: buf.len(),
+        };
+        match unsafe { fd_write(fd, &[iov]) } {
+            Ok(0) => return Err(EIO),
+            Ok(n) => buf = buf.get(n..).ok_or(EIO)?,
+            Err(EINTR) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// A handle to the standard output stream, `STDOUT_FD`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Stdout;
+
+/// A handle to the standard error stream, `STDERR_FD`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Stderr;
+
+impl Stdout {
+    /// Writes all of `buf`, retrying on short writes.
+    pub fn write_all(&mut self, buf: &[u8]) -> Result<(), Error> {
+        write_all(STDOUT_FD, buf)
+    }
+}
+
+impl Stderr {
+    /// Writes all of `buf`, retrying on short writes.
+    pub fn write_all(&mut self, buf: &[u8]) -> Result<(), Error> {
+        write_all(STDERR_FD, buf)
+    }
+}
+
+impl fmt::Write for Stdout {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_all(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+impl fmt::Write for Stderr {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_all(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+const LINE_BUF_LEN: usize = 1024;
+
+/// Buffers output to a writer until a line is complete.
+///
+/// Complete lines are handed to the inner writer in one piece as long as
+/// they fit in the buffer, so with `Stdout` or `Stderr` a line typically
+/// takes a single `fd_write`. Anything still buffered is written out when
+/// the `LineWriter` is dropped.
+pub struct LineWriter<W: fmt::Write> {
+    inner: W,
+    buf: [u8; LINE_BUF_LEN],
+    len: usize,
+}
+
+impl<W: fmt::Write> LineWriter<W> {
+    /// Creates a `LineWriter` writing to `inner`.
+    pub fn new(inner: W) -> Self {
+        LineWriter {
+            inner,
+            buf: [0; LINE_BUF_LEN],
+            len: 0,
+        }
+    }
+
+    /// Writes out anything that is buffered.
+    pub fn flush(&mut self) -> fmt::Result {
+        let len = self.len;
+        self.len = 0;
+        // Only whole strings are ever buffered, so this is valid UTF-8.
+        let s = unsafe { core::str::from_utf8_unchecked(&self.buf[..len]) };
+        self.inner.write_str(s)
+    }
+
+    /// Buffers `s`, writing it directly if it's too large for the buffer.
+    fn buffer(&mut self, s: &str) -> fmt::Result {
+        if self.len + s.len() > self.buf.len() {
+            self.flush()?;
+        }
+        if s.len() > self.buf.len() {
+            return self.inner.write_str(s);
+        }
+        self.buf[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+        self.len += s.len();
+        Ok(())
+    }
+
+    /// Returns a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+}
+
+impl<W: fmt::Write> fmt::Write for LineWriter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match s.rfind('\n') {
+            Some(i) => {
+                let (lines, rest) = s.split_at(i + 1);
+                self.buffer(lines)?;
+                self.flush()?;
+                self.buffer(rest)
+            }
+            None => self.buffer(s),
+        }
+    }
+}
+
+impl<W: fmt::Write> Drop for LineWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    let _ = fmt::Write::write_fmt(&mut LineWriter::new(Stdout), args);
+}
+
+#[doc(hidden)]
+pub fn _eprint(args: fmt::Arguments) {
+    let _ = fmt::Write::write_fmt(&mut LineWriter::new(Stderr), args);
+}
+
+/// Prints to the standard output, like `std::print!`.
+///
+/// Errors writing to the stream are ignored.
+#[macro_export]
+macro_rules! wasi_print {
+    ($($arg:tt)*) => {
+        $crate::wasi_unstable::stdio::_print(format_args!($($arg)*))
+    };
+}
+
+/// Prints to the standard output with a newline, like `std::println!`.
+///
+/// Errors writing to the stream are ignored.
+#[macro_export]
+macro_rules! wasi_println {
+    () => {
+        $crate::wasi_print!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::wasi_unstable::stdio::_print(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+/// Prints to the standard error, like `std::eprint!`.
+///
+/// Errors writing to the stream are ignored.
+#[macro_export]
+macro_rules! wasi_eprint {
+    ($($arg:tt)*) => {
+        $crate::wasi_unstable::stdio::_eprint(format_args!($($arg)*))
+    };
+}
+
+/// Prints to the standard error with a newline, like `std::eprintln!`.
+///
+/// Errors writing to the stream are ignored.
+#[macro_export]
+macro_rules! wasi_eprintln {
+    () => {
+        $crate::wasi_eprint!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::wasi_unstable::stdio::_eprint(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
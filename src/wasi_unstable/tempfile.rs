@@ -0,0 +1,170 @@
+//! Temporary files and directories, removed when dropped.
+//!
+//! [`tempfile`] and [`tempdir`] create an entry with a random name in a
+//! directory, using `random_get` for the name and `O_EXCL` so an existing
+//! entry is never reused. The returned handle borrows the directory, which
+//! keeps it open, and removes the entry through it when dropped.
+
+use super::fs::{create_unique, Dir, File, OpenOptions};
+use super::path::WasiPathBuf;
+use super::*;
+use core::ops::Deref;
+
+const PREFIX: &[u8] = b".tmp";
+
+/// A file removed when dropped, returned by [`tempfile`].
+#[derive(Debug)]
+pub struct TempFile<'a> {
+    parent: &'a Dir,
+    name: WasiPathBuf,
+    /// Only `None` while being dropped or kept.
+    file: Option<File>,
+}
+
+/// Creates an empty file with a unique name in `dir`, open for reading and
+/// writing.
+pub fn tempfile(dir: &Dir) -> Result<TempFile<'_>, Error> {
+    let (name, file) = create_unique(PREFIX, b"", |name| {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(dir, name)
+    })?;
+    Ok(TempFile {
+        parent: dir,
+        name,
+        file: Some(file),
+    })
+}
+
+impl TempFile<'_> {
+    /// Returns the open file.
+    pub fn file(&self) -> &File {
+        self.file.as_ref().expect("temporary file already closed")
+    }
+
+    /// Returns the file's name in the directory it was created in.
+    pub fn name(&self) -> &WasiPath {
+        &self.name
+    }
+
+    /// Keeps the file instead of removing it, returning it along with its
+    /// name.
+    pub fn keep(mut self) -> (File, WasiPathBuf) {
+        let file = self.file.take().expect("temporary file already closed");
+        (file, core::mem::take(&mut self.name))
+    }
+
+    /// Closes and removes the file, returning the error that removing it
+    /// failed with, which dropping it ignores.
+    pub fn close(mut self) -> Result<(), Error> {
+        self.remove()
+    }
+
+    fn remove(&mut self) -> Result<(), Error> {
+        match self.file.take() {
+            // Close the file first, some hosts can't remove open files.
+            Some(file) => {
+                drop(file);
+                self.parent.remove_file(&self.name)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Deref for TempFile<'_> {
+    type Target = File;
+
+    fn deref(&self) -> &File {
+        self.file()
+    }
+}
+
+impl Drop for TempFile<'_> {
+    fn drop(&mut self) {
+        let _ = self.remove();
+    }
+}
+
+/// A directory removed along with its contents when dropped, returned by
+/// [`tempdir`].
+#[derive(Debug)]
+pub struct TempDir<'a> {
+    parent: &'a Dir,
+    name: WasiPathBuf,
+    /// Only `None` while being dropped or kept.
+    dir: Option<Dir>,
+}
+
+/// Creates an empty directory with a unique name in `dir`.
+pub fn tempdir(dir: &Dir) -> Result<TempDir<'_>, Error> {
+    let (name, ()) = create_unique(PREFIX, b"", |name| dir.create_dir(name))?;
+    let opened = OpenOptions::new()
+        .follow_symlinks(false)
+        .open_dir(dir, &name);
+    let opened = match opened {
+        Ok(opened) => opened,
+        Err(err) => {
+            let _ = dir.remove_dir(&name);
+            return Err(err);
+        }
+    };
+    Ok(TempDir {
+        parent: dir,
+        name,
+        dir: Some(opened),
+    })
+}
+
+impl TempDir<'_> {
+    /// Returns the open directory.
+    pub fn dir(&self) -> &Dir {
+        self.dir
+            .as_ref()
+            .expect("temporary directory already closed")
+    }
+
+    /// Returns the directory's name in the directory it was created in.
+    pub fn name(&self) -> &WasiPath {
+        &self.name
+    }
+
+    /// Keeps the directory instead of removing it, returning it along with
+    /// its name.
+    pub fn keep(mut self) -> (Dir, WasiPathBuf) {
+        let dir = self.dir.take().expect("temporary directory already closed");
+        (dir, core::mem::take(&mut self.name))
+    }
+
+    /// Closes and removes the directory and its contents, returning the
+    /// error that removing them failed with, which dropping it ignores.
+    pub fn close(mut self) -> Result<(), Error> {
+        self.remove()
+    }
+
+    fn remove(&mut self) -> Result<(), Error> {
+        match self.dir.take() {
+            Some(dir) => {
+                drop(dir);
+                self.parent.remove_dir_all(&self.name)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Deref for TempDir<'_> {
+    type Target = Dir;
+
+    fn deref(&self) -> &Dir {
+        self.dir()
+    }
+}
+
+impl Drop for TempDir<'_> {
+    fn drop(&mut self) {
+        let _ = self.remove();
+    }
+}
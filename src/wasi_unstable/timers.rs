@@ -0,0 +1,206 @@
+//! Deadlines for an event loop built on `poll_oneoff`.
+//!
+//! A [`Timers`] keeps the pending deadlines on one clock, keyed by
+//! `Userdata`, sorted by deadline. Rather than adding a clock subscription
+//! per timer to every `poll_oneoff` call, an event loop adds the single one
+//! returned by [`Timers::subscription`], which fires at the nearest
+//! deadline, and then calls [`Timers::expire`] to dispatch every timer whose
+//! deadline has passed.
+
+use super::raw::{__wasi_subscription_clock_t, __wasi_subscription_u};
+use super::*;
+use alloc::vec::Vec;
+
+#[derive(Copy, Clone, Debug)]
+struct Timer {
+    deadline: Timestamp,
+    userdata: Userdata,
+}
+
+/// Pending deadlines on a clock, keyed by `Userdata`.
+#[derive(Clone, Debug)]
+pub struct Timers {
+    clock_id: ClockId,
+    precision: Timestamp,
+    /// Sorted by descending deadline, so the nearest one is last. Timers
+    /// with the same deadline are kept in the order they were inserted in,
+    /// the first one last.
+    timers: Vec<Timer>,
+}
+
+impl Timers {
+    /// Creates an empty set of timers on `clock_id`.
+    pub const fn new(clock_id: ClockId) -> Self {
+        Timers {
+            clock_id,
+            precision: 0,
+            timers: Vec::new(),
+        }
+    }
+
+    /// Sets the precision requested for the clock subscription and when
+    /// reading the clock.
+    pub fn set_precision(&mut self, precision: Timestamp) {
+        self.precision = precision;
+    }
+
+    /// Returns the clock the deadlines are on.
+    pub fn clock_id(&self) -> ClockId {
+        self.clock_id
+    }
+
+    /// Returns the number of pending timers.
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    /// Returns whether there are no pending timers.
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    /// Adds a timer expiring at `deadline`, an absolute time on the clock.
+    ///
+    /// A pending timer with the same `userdata` is replaced.
+    pub fn insert(&mut self, userdata: Userdata, deadline: Timestamp) {
+        self.cancel(userdata);
+        let index = self.timers.partition_point(|t| t.deadline > deadline);
+        self.timers.insert(index, Timer { deadline, userdata });
+    }
+
+    /// Cancels the timer with `userdata`, returning its deadline if it was
+    /// pending.
+    pub fn cancel(&mut self, userdata: Userdata) -> Option<Timestamp> {
+        let index = self.timers.iter().position(|t| t.userdata == userdata)?;
+        Some(self.timers.remove(index).deadline)
+    }
+
+    /// Returns the deadline of the timer with `userdata`, if it is pending.
+    pub fn deadline(&self, userdata: Userdata) -> Option<Timestamp> {
+        self.timers
+            .iter()
+            .find(|t| t.userdata == userdata)
+            .map(|t| t.deadline)
+    }
+
+    /// Returns the nearest deadline and the timer it belongs to.
+    pub fn next(&self) -> Option<(Userdata, Timestamp)> {
+        self.timers.last().map(|t| (t.userdata, t.deadline))
+    }
+
+    /// Returns the clock subscription to pass to `poll_oneoff`, if any
+    /// timer is pending.
+    ///
+    /// It fires at the nearest deadline, using `SUBSCRIPTION_CLOCK_ABSTIME`,
+    /// and carries the `userdata` of that deadline's timer.
+    pub fn subscription(&self) -> Option<Subscription> {
+        let (userdata, deadline) = self.next()?;
+        Some(Subscription {
+            userdata,
+            r#type: EVENTTYPE_CLOCK,
+            u: __wasi_subscription_u {
+                clock: __wasi_subscription_clock_t {
+                    identifier: 0,
+                    clock_id: self.clock_id,
+                    timeout: deadline,
+                    precision: self.precision,
+                    flags: SUBSCRIPTION_CLOCK_ABSTIME,
+                },
+            },
+        })
+    }
+
+    /// Removes the timers whose deadline is at or before `now`, calling
+    /// `expired` with each one's `userdata`, nearest deadline first.
+    ///
+    /// Returns the number of expired timers.
+    pub fn expire(&mut self, now: Timestamp, mut expired: impl FnMut(Userdata)) -> usize {
+        let mut n = 0;
+        while let Some(timer) = self.timers.last() {
+            if timer.deadline > now {
+                break;
+            }
+            expired(timer.userdata);
+            self.timers.pop();
+            n += 1;
+        }
+        n
+    }
+
+    /// Reads the clock and calls [`expire`](Timers::expire) with the
+    /// current time.
+    pub fn expire_now(&mut self, expired: impl FnMut(Userdata)) -> Result<usize, Error> {
+        let now = clock_time_get(self.clock_id, self.precision)?;
+        Ok(self.expire(now, expired))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    fn expire(timers: &mut Timers, now: Timestamp) -> Vec<Userdata> {
+        let mut expired = Vec::new();
+        let n = timers.expire(now, |userdata| expired.push(userdata));
+        assert_eq!(n, expired.len());
+        expired
+    }
+
+    #[test]
+    fn expire_in_deadline_order() {
+        let mut timers = Timers::new(CLOCK_MONOTONIC);
+        timers.insert(1, 30);
+        timers.insert(2, 10);
+        timers.insert(3, 20);
+        assert_eq!(timers.next(), Some((2, 10)));
+        assert_eq!(expire(&mut timers, 5), []);
+        assert_eq!(expire(&mut timers, 20), [2, 3]);
+        assert_eq!(expire(&mut timers, 100), [1]);
+        assert!(timers.is_empty());
+        assert_eq!(timers.next(), None);
+    }
+
+    #[test]
+    fn same_deadline_in_insertion_order() {
+        let mut timers = Timers::new(CLOCK_MONOTONIC);
+        timers.insert(1, 10);
+        timers.insert(2, 10);
+        timers.insert(3, 5);
+        timers.insert(4, 10);
+        assert_eq!(expire(&mut timers, 10), [3, 1, 2, 4]);
+    }
+
+    #[test]
+    fn cancel_and_replace() {
+        let mut timers = Timers::new(CLOCK_MONOTONIC);
+        timers.insert(1, 10);
+        timers.insert(2, 20);
+        assert_eq!(timers.cancel(1), Some(10));
+        assert_eq!(timers.cancel(1), None);
+        assert_eq!(timers.deadline(1), None);
+        assert_eq!(timers.len(), 1);
+
+        timers.insert(2, 5);
+        assert_eq!(timers.len(), 1);
+        assert_eq!(timers.deadline(2), Some(5));
+        assert_eq!(expire(&mut timers, 5), [2]);
+    }
+
+    #[test]
+    fn subscription() {
+        let mut timers = Timers::new(CLOCK_REALTIME);
+        assert!(timers.subscription().is_none());
+        timers.set_precision(3);
+        timers.insert(7, 20);
+        timers.insert(8, 10);
+        let sub = timers.subscription().unwrap();
+        assert_eq!(sub.userdata, 8);
+        assert_eq!(sub.r#type, EVENTTYPE_CLOCK);
+        let clock = unsafe { sub.u.clock };
+        assert_eq!(clock.clock_id, CLOCK_REALTIME);
+        assert_eq!(clock.timeout, 10);
+        assert_eq!(clock.precision, 3);
+        assert_eq!(clock.flags, SUBSCRIPTION_CLOCK_ABSTIME);
+    }
+}
@@ -0,0 +1,454 @@
+//! Tracing of the calls made through the `wasi_unstable` wrappers.
+//!
+//! When the `trace` feature is enabled every wrapper in the parent module
+//! reports its name, its decoded arguments and its result to a [`Sink`].
+//! Until a sink is installed with [`set_sink`], calls are reported to
+//! [`StderrSink`], which writes strace-style lines such as
+//!
+//! ```text
+//! path_open(dirfd=3, dirflags=SYMLINK_FOLLOW, path="data/in.txt", oflags=0, ...) = 4
+//! fd_read(fd=4, iovs=[1 iovs, 4096 bytes]) = -1 EBADF (Bad file descriptor)
+//! ```
+//!
+//! Calls made while a sink is running are not reported, so sinks are free to
+//! use the functions in this crate themselves.
+
+use super::*;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// A decoded argument of a traced call.
+#[derive(Copy, Clone)]
+pub enum Arg<'a> {
+    /// A file descriptor.
+    Fd(Fd),
+    /// An unsigned integer such as a size, an offset or a timestamp.
+    Int(u64),
+    /// A signed integer, used for relative seek offsets.
+    Delta(i64),
+    /// A path, printed as a string.
+    Path(&'a [u8]),
+    /// A buffer of which only the length is reported.
+    Buf(usize),
+    /// An array of iovecs, reported as the number of buffers and their
+    /// total length.
+    IoVecs(usize, usize),
+    /// A bitset along with the names of its individual flags.
+    Flags(u64, &'static [(u64, &'static str)]),
+    /// An enumerated value along with the names of its variants.
+    Enum(u64, &'static [(u64, &'static str)]),
+}
+
+/// The successful result of a traced call.
+#[derive(Copy, Clone)]
+pub enum Ret {
+    /// The call returned no value, or a structure which isn't decoded.
+    Done,
+    /// The call returned a number, such as a file descriptor or a byte count.
+    Value(u64),
+    /// The call doesn't return, as is the case for `proc_exit`.
+    NoReturn,
+}
+
+/// A single traced call.
+pub struct Call<'a> {
+    /// The name of the function, e.g. `"fd_read"`.
+    pub name: &'static str,
+    /// The names and decoded values of the arguments.
+    pub args: &'a [(&'static str, Arg<'a>)],
+    /// The result of the call.
+    pub result: Result<Ret, Error>,
+}
+
+/// A destination for traced calls.
+pub trait Sink: Sync {
+    /// Called after every traced call returns, or just before `proc_exit`.
+    fn call(&self, call: &Call<'_>);
+}
+
+/// The default sink, which writes one line per call to `STDERR_FD`.
+///
+/// Lines longer than an internal fixed-size buffer are truncated and end
+/// with `...`; no allocation is performed.
+///
+/// Lines are written with the raw `fd_write` import, so they are never
+/// recorded or replayed. When replaying outside of wasm there is no import
+/// to call, so they go to the native stderr with the `std` feature and are
+/// dropped without it.
+pub struct StderrSink;
+
+const UNINITIALIZED: usize = 0;
+const INITIALIZING: usize = 1;
+const INITIALIZED: usize = 2;
+
+static STATE: AtomicUsize = AtomicUsize::new(UNINITIALIZED);
+static mut SINK: &dyn Sink = &StderrSink;
+static IN_SINK: AtomicBool = AtomicBool::new(false);
+
+/// Installs the sink which traced calls are reported to.
+///
+/// The sink can only be installed once; later calls fail with `EALREADY`.
+pub fn set_sink(sink: &'static dyn Sink) -> Result<(), Error> {
+    match STATE.compare_exchange(
+        UNINITIALIZED,
+        INITIALIZING,
+        Ordering::SeqCst,
+        Ordering::SeqCst,
+    ) {
+        Ok(_) => {
+            unsafe {
+                SINK = sink;
+            }
+            STATE.store(INITIALIZED, Ordering::SeqCst);
+            Ok(())
+        }
+        Err(_) => Err(EALREADY),
+    }
+}
+
+/// Returns the sink which traced calls are currently reported to.
+pub fn sink() -> &'static dyn Sink {
+    if STATE.load(Ordering::SeqCst) == INITIALIZED {
+        unsafe { SINK }
+    } else {
+        &StderrSink
+    }
+}
+
+pub(crate) trait TraceRet {
+    fn trace_ret(&self) -> Ret;
+}
+
+impl TraceRet for () {
+    fn trace_ret(&self) -> Ret {
+        Ret::Done
+    }
+}
+
+impl TraceRet for usize {
+    fn trace_ret(&self) -> Ret {
+        Ret::Value(*self as u64)
+    }
+}
+
+impl TraceRet for u32 {
+    fn trace_ret(&self) -> Ret {
+        Ret::Value(u64::from(*self))
+    }
+}
+
+impl TraceRet for u64 {
+    fn trace_ret(&self) -> Ret {
+        Ret::Value(*self)
+    }
+}
+
+impl TraceRet for (usize, RoFlags) {
+    fn trace_ret(&self) -> Ret {
+        Ret::Value(self.0 as u64)
+    }
+}
+
+macro_rules! trace_ret_done {
+    ($($t:ty),*) => {
+        $(
+            impl TraceRet for $t {
+                fn trace_ret(&self) -> Ret {
+                    Ret::Done
+                }
+            }
+        )*
+    };
+}
+
+trace_ret_done!(FdStat, FileStat, Prestat, ArgsSizes, EnvironSizes);
+
+pub(crate) fn record<T: TraceRet>(
+    name: &'static str,
+    args: &[(&'static str, Arg<'_>)],
+    ret: &Result<T, Error>,
+) {
+    let result = match ret {
+        Ok(v) => Ok(v.trace_ret()),
+        Err(e) => Err(*e),
+    };
+    dispatch(&Call { name, args, result });
+}
+
+pub(crate) fn record_noreturn(name: &'static str, args: &[(&'static str, Arg<'_>)]) {
+    dispatch(&Call {
+        name,
+        args,
+        result: Ok(Ret::NoReturn),
+    });
+}
+
+fn dispatch(call: &Call<'_>) {
+    if IN_SINK.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    sink().call(call);
+    IN_SINK.store(false, Ordering::SeqCst);
+}
+
+/// Constructors of [`Arg`] used by the wrappers in the parent module.
+pub(crate) mod arg {
+    use super::*;
+
+    pub fn fd(v: &Fd) -> Arg<'static> {
+        Arg::Fd(*v)
+    }
+    pub fn int<T: Copy + Into<u64>>(v: &T) -> Arg<'static> {
+        Arg::Int((*v).into())
+    }
+    pub fn delta(v: &FileDelta) -> Arg<'static> {
+        Arg::Delta(*v)
+    }
+    pub fn path<'a>(v: &&'a [u8]) -> Arg<'a> {
+        Arg::Path(v)
+    }
+    pub fn buf(v: &&mut [u8]) -> Arg<'static> {
+        Arg::Buf(v.len())
+    }
+    pub fn iovs(v: &&[IoVec]) -> Arg<'static> {
+        Arg::IoVecs(v.len(), v.iter().map(|iov| iov.buf_len).sum())
+    }
+    pub fn ciovs(v: &&[CIoVec]) -> Arg<'static> {
+        Arg::IoVecs(v.len(), v.iter().map(|iov| iov.buf_len).sum())
+    }
+    pub fn subs(v: &&[Subscription]) -> Arg<'static> {
+        Arg::Buf(v.len())
+    }
+    pub fn events(v: &&mut [Event]) -> Arg<'static> {
+        Arg::Buf(v.len())
+    }
+    pub fn rights(v: &Rights) -> Arg<'static> {
+        Arg::Flags(*v, RIGHTS)
+    }
+    pub fn fdflags(v: &FdFlags) -> Arg<'static> {
+        Arg::Flags((*v).into(), FDFLAGS)
+    }
+    pub fn fstflags(v: &FstFlags) -> Arg<'static> {
+        Arg::Flags((*v).into(), FSTFLAGS)
+    }
+    pub fn lookupflags(v: &LookupFlags) -> Arg<'static> {
+        Arg::Flags((*v).into(), LOOKUPFLAGS)
+    }
+    pub fn oflags(v: &OFlags) -> Arg<'static> {
+        Arg::Flags((*v).into(), OFLAGS)
+    }
+    pub fn riflags(v: &RiFlags) -> Arg<'static> {
+        Arg::Flags((*v).into(), RIFLAGS)
+    }
+    pub fn siflags(v: &SiFlags) -> Arg<'static> {
+        Arg::Flags((*v).into(), &[])
+    }
+    pub fn sdflags(v: &SdFlags) -> Arg<'static> {
+        Arg::Flags((*v).into(), SDFLAGS)
+    }
+    pub fn advice(v: &Advice) -> Arg<'static> {
+        Arg::Enum((*v).into(), ADVICES)
+    }
+    pub fn clockid(v: &ClockId) -> Arg<'static> {
+        Arg::Enum((*v).into(), CLOCKIDS)
+    }
+    pub fn whence(v: &Whence) -> Arg<'static> {
+        Arg::Enum((*v).into(), WHENCES)
+    }
+    pub fn signal(v: &Signal) -> Arg<'static> {
+        Arg::Enum((*v).into(), SIGNALS)
+    }
+}
+
+macro_rules! names {
+    ($name:ident: $($value:ident),* $(,)?) => {
+        const $name: &[(u64, &str)] = &[$(($value as u64, stringify!($value))),*];
+    };
+}
+
+names!(ADVICES: ADVICE_NORMAL, ADVICE_SEQUENTIAL, ADVICE_RANDOM, ADVICE_WILLNEED,
+       ADVICE_DONTNEED, ADVICE_NOREUSE);
+names!(CLOCKIDS: CLOCK_REALTIME, CLOCK_MONOTONIC, CLOCK_PROCESS_CPUTIME_ID,
+       CLOCK_THREAD_CPUTIME_ID);
+names!(WHENCES: WHENCE_CUR, WHENCE_END, WHENCE_SET);
+names!(FDFLAGS: FDFLAG_APPEND, FDFLAG_DSYNC, FDFLAG_NONBLOCK, FDFLAG_RSYNC, FDFLAG_SYNC);
+names!(FSTFLAGS: FILESTAT_SET_ATIM, FILESTAT_SET_ATIM_NOW, FILESTAT_SET_MTIM,
+       FILESTAT_SET_MTIM_NOW);
+names!(LOOKUPFLAGS: LOOKUP_SYMLINK_FOLLOW);
+names!(OFLAGS: O_CREAT, O_DIRECTORY, O_EXCL, O_TRUNC);
+names!(RIFLAGS: SOCK_RECV_PEEK, SOCK_RECV_WAITALL);
+names!(SDFLAGS: SHUT_RD, SHUT_WR);
+names!(SIGNALS: SIGHUP, SIGINT, SIGQUIT, SIGILL, SIGTRAP, SIGABRT, SIGBUS, SIGFPE, SIGKILL,
+       SIGUSR1, SIGSEGV, SIGUSR2, SIGPIPE, SIGALRM, SIGTERM, SIGCHLD, SIGCONT, SIGSTOP,
+       SIGTSTP, SIGTTIN, SIGTTOU, SIGURG, SIGXCPU, SIGXFSZ, SIGVTALRM, SIGPROF, SIGWINCH,
+       SIGPOLL, SIGPWR, SIGSYS);
+const RIGHTS: &[(u64, &str)] = super::rights::RIGHT_NAMES;
+
+/// Strips the common prefix of a constant's name, e.g. `O_CREAT` becomes
+/// `CREAT`, so that the trace output stays readable.
+fn short_name(name: &str) -> &str {
+    const PREFIXES: &[&str] = &[
+        "ADVICE_",
+        "CLOCK_",
+        "WHENCE_",
+        "FDFLAG_",
+        "FILESTAT_",
+        "LOOKUP_",
+        "O_",
+        "SOCK_RECV_",
+        "SHUT_",
+        "RIGHT_",
+    ];
+    PREFIXES
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+impl fmt::Display for Arg<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Arg::Fd(fd) => write!(f, "{}", fd),
+            Arg::Int(v) => write!(f, "{}", v),
+            Arg::Delta(v) => write!(f, "{}", v),
+            Arg::Path(path) => {
+                f.write_char('"')?;
+                for chunk in path.utf8_chunks() {
+                    for c in chunk.valid().chars() {
+                        match c {
+                            '"' | '\\' => write!(f, "\\{}", c)?,
+                            c if c.is_control() => write!(f, "{}", c.escape_default())?,
+                            c => f.write_char(c)?,
+                        }
+                    }
+                    for b in chunk.invalid() {
+                        write!(f, "\\x{:02x}", b)?;
+                    }
+                }
+                f.write_char('"')
+            }
+            Arg::Buf(len) => write!(f, "[{}]", len),
+            Arg::IoVecs(count, len) => write!(f, "[{} iovs, {} bytes]", count, len),
+            Arg::Flags(bits, names) => {
+                if bits == 0 {
+                    return f.write_char('0');
+                }
+                let mut rest = bits;
+                let mut first = true;
+                for &(flag, name) in names {
+                    if bits & flag == flag {
+                        if !first {
+                            f.write_char('|')?;
+                        }
+                        f.write_str(short_name(name))?;
+                        rest &= !flag;
+                        first = false;
+                    }
+                }
+                if rest != 0 {
+                    if !first {
+                        f.write_char('|')?;
+                    }
+                    write!(f, "{:#x}", rest)?;
+                }
+                Ok(())
+            }
+            Arg::Enum(value, names) => match names.iter().find(|&&(v, _)| v == value) {
+                Some(&(_, name)) => f.write_str(short_name(name)),
+                None => write!(f, "{}", value),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Call<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}(", self.name)?;
+        for (i, (name, arg)) in self.args.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}={}", name, arg)?;
+        }
+        f.write_str(") = ")?;
+        match self.result {
+            Ok(Ret::Done) => f.write_char('0'),
+            Ok(Ret::Value(v)) => write!(f, "{}", v),
+            Ok(Ret::NoReturn) => f.write_char('?'),
+            Err(e) => {
+                write!(f, "-1 {}", error_name(e).unwrap_or("E?"))?;
+                match error_str(e) {
+                    Some(desc) => write!(f, " ({})", desc),
+                    None => write!(f, " ({})", e),
+                }
+            }
+        }
+    }
+}
+
+/// A line of trace output which silently truncates once it is full.
+struct Line {
+    buf: [u8; 512],
+    len: usize,
+    truncated: bool,
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // Leave room for the trailing "...\n".
+        let avail = self.buf.len() - 4 - self.len;
+        let n = s.len().min(avail);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        if n < s.len() {
+            self.truncated = true;
+        }
+        Ok(())
+    }
+}
+
+impl Sink for StderrSink {
+    fn call(&self, call: &Call<'_>) {
+        let mut line = Line {
+            buf: [0; 512],
+            len: 0,
+            truncated: false,
+        };
+        let _ = write!(line, "{}", call);
+        if line.truncated {
+            line.buf[line.len..line.len + 3].copy_from_slice(b"...");
+            line.len += 3;
+        }
+        line.buf[line.len] = b'\n';
+        line.len += 1;
+
+        write_stderr(&line.buf[..line.len]);
+    }
+}
+
+/// Writes `data` to `STDERR_FD` with the raw import, so the write is neither
+/// traced itself nor recorded or replayed.
+#[cfg(any(not(feature = "replay"), target_arch = "wasm32"))]
+fn write_stderr(mut data: &[u8]) {
+    while !data.is_empty() {
+        let iov = CIoVec {
+            buf: data.as_ptr(),
+            buf_len: data.len(),
+        };
+        let mut nwritten = 0;
+        let r = unsafe { raw::__wasi_fd_write(STDERR_FD, &iov, 1, &mut nwritten) };
+        if r != __WASI_ESUCCESS || nwritten == 0 {
+            break;
+        }
+        data = &data[nwritten..];
+    }
+}
+
+#[cfg(all(feature = "replay", not(target_arch = "wasm32"), feature = "std"))]
+fn write_stderr(data: &[u8]) {
+    use std::io::Write as _;
+    let _ = std::io::stderr().write_all(data);
+}
+
+#[cfg(all(feature = "replay", not(target_arch = "wasm32"), not(feature = "std")))]
+fn write_stderr(_data: &[u8]) {}
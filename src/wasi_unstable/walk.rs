@@ -0,0 +1,401 @@
+//! Recursive traversal of a directory tree.
+//!
+//! [`walk_dir`] returns an iterator over every entry below a directory,
+//! reading each directory with `fd_readdir` and descending into
+//! subdirectories with `path_open`. A subdirectory's descriptor is closed as
+//! soon as all of its entries have been returned, so at most one descriptor
+//! per level of the tree is open at a time.
+//!
+//! Errors, such as a subdirectory that can't be opened, are returned as
+//! [`WalkError`] items and the walk carries on with the next entry.
+
+use super::fs::{Dir, DirReader, OpenOptions};
+use super::path::WasiPathBuf;
+use super::*;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+
+type Filter<'a> = Box<dyn FnMut(&WalkEntry) -> bool + 'a>;
+
+/// Options for [`walk_dir`].
+pub struct WalkOptions<'a> {
+    max_depth: usize,
+    follow_symlinks: bool,
+    contents_first: bool,
+    filter: Option<Filter<'a>>,
+}
+
+impl Default for WalkOptions<'_> {
+    fn default() -> Self {
+        WalkOptions::new()
+    }
+}
+
+impl fmt::Debug for WalkOptions<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WalkOptions")
+            .field("max_depth", &self.max_depth)
+            .field("follow_symlinks", &self.follow_symlinks)
+            .field("contents_first", &self.contents_first)
+            .field("filter", &self.filter.is_some())
+            .finish()
+    }
+}
+
+impl<'a> WalkOptions<'a> {
+    /// Creates options for walking the whole tree in pre-order, without
+    /// following symbolic links.
+    pub fn new() -> Self {
+        WalkOptions {
+            max_depth: usize::MAX,
+            follow_symlinks: false,
+            contents_first: false,
+            filter: None,
+        }
+    }
+
+    /// Only returns entries up to `depth` levels below the directory.
+    /// The entries directly in the directory are at depth 1, so a
+    /// `depth` of 0 returns nothing.
+    pub fn max_depth(self, depth: usize) -> Self {
+        WalkOptions {
+            max_depth: depth,
+            ..self
+        }
+    }
+
+    /// Follows symbolic links, reporting the type of their target and
+    /// descending into the directories they point to.
+    ///
+    /// A symbolic link whose target can't be examined, such as a dangling
+    /// one, is still reported as a symbolic link. A link to one of the
+    /// directories being walked is reported as an `ELOOP` error.
+    pub fn follow_symlinks(self, follow: bool) -> Self {
+        WalkOptions {
+            follow_symlinks: follow,
+            ..self
+        }
+    }
+
+    /// Returns a directory after its contents, rather than before.
+    pub fn contents_first(self, contents_first: bool) -> Self {
+        WalkOptions {
+            contents_first,
+            ..self
+        }
+    }
+
+    /// Skips the entries for which `filter` returns false, along with
+    /// everything below them.
+    pub fn filter(self, filter: impl FnMut(&WalkEntry) -> bool + 'a) -> Self {
+        WalkOptions {
+            filter: Some(Box::new(filter)),
+            ..self
+        }
+    }
+}
+
+/// An entry found while walking a directory tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WalkEntry {
+    path: Vec<u8>,
+    name_start: usize,
+    depth: usize,
+    file_type: FileType,
+    ino: Inode,
+}
+
+impl WalkEntry {
+    /// Returns the entry's path relative to the walked directory, with
+    /// components separated by `/`.
+    pub fn path(&self) -> &WasiPath {
+        WasiPath::from_bytes_unchecked(&self.path)
+    }
+
+    /// Returns the entry's path, consuming the entry.
+    pub fn into_path(self) -> WasiPathBuf {
+        WasiPathBuf::from_bytes_unchecked(self.path)
+    }
+
+    /// Returns the entry's name, the last component of its path.
+    pub fn file_name(&self) -> &[u8] {
+        &self.path[self.name_start..]
+    }
+
+    /// Returns how far below the walked directory the entry is, 1 for the
+    /// entries directly in it.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns the type of the file, one of the `FILETYPE_*` constants.
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    /// Returns whether the entry is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.file_type == FILETYPE_DIRECTORY
+    }
+
+    /// Returns whether the entry is a symbolic link.
+    pub fn is_symlink(&self) -> bool {
+        self.file_type == FILETYPE_SYMBOLIC_LINK
+    }
+
+    /// Returns the inode reported by `fd_readdir`.
+    pub fn ino(&self) -> Inode {
+        self.ino
+    }
+}
+
+/// An error encountered while walking a directory tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WalkError {
+    path: Vec<u8>,
+    depth: usize,
+    error: Error,
+}
+
+impl WalkError {
+    /// Returns the path of the entry or directory the error is about,
+    /// relative to the walked directory. It is empty for the walked
+    /// directory itself.
+    pub fn path(&self) -> &WasiPath {
+        WasiPath::from_bytes_unchecked(&self.path)
+    }
+
+    /// Returns the depth of the entry or directory the error is about.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns the error.
+    pub fn error(&self) -> Error {
+        self.error
+    }
+}
+
+/// A directory being read.
+struct Frame {
+    /// The directory, unless it's the walked one.
+    dir: Option<Dir>,
+    reader: DirReader,
+    /// The length of the parent directory's path.
+    parent_path_len: usize,
+    /// The depth of the entries in the directory.
+    depth: usize,
+    /// The device and inode of the directory, when following symbolic links.
+    id: Option<(Device, Inode)>,
+    /// The directory's own entry, returned once its contents are, in
+    /// contents-first order.
+    entry: Option<WalkEntry>,
+}
+
+/// An iterator over the entries below a directory, returned by
+/// [`walk_dir`].
+pub struct WalkDir<'a> {
+    root: &'a Dir,
+    options: WalkOptions<'a>,
+    stack: Vec<Frame>,
+    /// The path of the directory at the top of the stack.
+    path: Vec<u8>,
+    /// An item to return before reading further.
+    pending: Option<Result<WalkEntry, WalkError>>,
+    started: bool,
+}
+
+impl fmt::Debug for WalkDir<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WalkDir")
+            .field("root", &self.root)
+            .field("options", &self.options)
+            .field("depth", &self.stack.len())
+            .finish()
+    }
+}
+
+/// Returns an iterator over the entries below `dir`, recursively.
+///
+/// `.` and `..` are skipped, and the entries of a directory are returned in
+/// the order `fd_readdir` reports them.
+pub fn walk_dir<'a>(dir: &'a Dir, options: WalkOptions<'a>) -> WalkDir<'a> {
+    WalkDir {
+        root: dir,
+        options,
+        stack: Vec::new(),
+        path: Vec::new(),
+        pending: None,
+        started: false,
+    }
+}
+
+fn dir_id(fd: Fd) -> Result<(Device, Inode), Error> {
+    let stat = unsafe { fd_filestat_get(fd)? };
+    Ok((stat.st_dev, stat.st_ino))
+}
+
+impl WalkDir<'_> {
+    fn start(&mut self) -> Option<WalkError> {
+        self.started = true;
+        let fd = self.root.as_raw_fd();
+        let mut id = None;
+        let mut error = None;
+        if self.options.follow_symlinks {
+            match dir_id(fd) {
+                Ok(root) => id = Some(root),
+                Err(err) => error = Some(err),
+            }
+        }
+        self.stack.push(Frame {
+            dir: None,
+            reader: DirReader::new(fd),
+            parent_path_len: 0,
+            depth: 1,
+            id,
+            entry: None,
+        });
+        error.map(|error| WalkError {
+            path: Vec::new(),
+            depth: 0,
+            error,
+        })
+    }
+
+    /// Opens the directory `entry` and starts reading it.
+    fn descend(&mut self, dirfd: Fd, entry: &WalkEntry) -> Result<(), WalkError> {
+        let error = |error| WalkError {
+            path: entry.path.clone(),
+            depth: entry.depth,
+            error,
+        };
+        let mut options = OpenOptions::new();
+        options
+            .directory(true)
+            .follow_symlinks(self.options.follow_symlinks);
+        let fd =
+            unsafe { options.open_fd(dirfd, WasiPath::from_bytes_unchecked(entry.file_name())) }
+                .map_err(error)?;
+        let dir = unsafe { Dir::from_raw_fd(fd) };
+        let id = if self.options.follow_symlinks {
+            let id = dir_id(fd).map_err(error)?;
+            if self.stack.iter().any(|frame| frame.id == Some(id)) {
+                return Err(error(ELOOP));
+            }
+            Some(id)
+        } else {
+            None
+        };
+        let parent_path_len = self.path.len();
+        self.path.clear();
+        self.path.extend_from_slice(&entry.path);
+        let contents_first = self.options.contents_first;
+        self.stack.push(Frame {
+            dir: Some(dir),
+            reader: DirReader::new(fd),
+            parent_path_len,
+            depth: entry.depth + 1,
+            id,
+            entry: if contents_first {
+                Some(entry.clone())
+            } else {
+                None
+            },
+        });
+        Ok(())
+    }
+}
+
+impl Iterator for WalkDir<'_> {
+    type Item = Result<WalkEntry, WalkError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(item) = self.pending.take() {
+            return Some(item);
+        }
+        // Subdirectories are only opened below `max_depth`, so only the
+        // entries of the walked directory itself can be too deep.
+        if self.options.max_depth == 0 {
+            return None;
+        }
+        if !self.started {
+            if let Some(err) = self.start() {
+                return Some(Err(err));
+            }
+        }
+        loop {
+            let frame = self.stack.last_mut()?;
+            let dirfd = frame.dir.as_ref().unwrap_or(self.root).as_raw_fd();
+            let depth = frame.depth;
+            let dirent = match frame.reader.next_entry() {
+                Some(Ok(dirent)) => dirent,
+                Some(Err(error)) => {
+                    // The reader is done, the directory is popped next time.
+                    let path = self.path.clone();
+                    return Some(Err(WalkError {
+                        path,
+                        depth: depth - 1,
+                        error,
+                    }));
+                }
+                None => {
+                    // Closes the directory.
+                    let frame = self.stack.pop()?;
+                    self.path.truncate(frame.parent_path_len);
+                    match frame.entry {
+                        Some(entry) => return Some(Ok(entry)),
+                        None => continue,
+                    }
+                }
+            };
+            if dirent.is_dot() {
+                continue;
+            }
+
+            let mut path = self.path.clone();
+            if !path.is_empty() {
+                path.push(b'/');
+            }
+            let name_start = path.len();
+            path.extend_from_slice(dirent.file_name());
+            let mut file_type = dirent.file_type();
+            if self.options.follow_symlinks && file_type == FILETYPE_SYMBOLIC_LINK {
+                let name = WasiPath::from_bytes_unchecked(dirent.file_name());
+                if let Ok(stat) = unsafe { path_filestat_get(dirfd, LOOKUP_SYMLINK_FOLLOW, name) } {
+                    file_type = stat.st_filetype;
+                }
+            }
+            let entry = WalkEntry {
+                path,
+                name_start,
+                depth,
+                file_type,
+                ino: dirent.ino(),
+            };
+
+            if let Some(filter) = &mut self.options.filter {
+                if !filter(&entry) {
+                    continue;
+                }
+            }
+            if !entry.is_dir() || depth >= self.options.max_depth {
+                return Some(Ok(entry));
+            }
+            let contents_first = self.options.contents_first;
+            match self.descend(dirfd, &entry) {
+                Ok(()) if contents_first => continue,
+                Ok(()) => return Some(Ok(entry)),
+                // Report both the directory and the failure to open it.
+                Err(err) if contents_first => {
+                    self.pending = Some(Ok(entry));
+                    return Some(Err(err));
+                }
+                Err(err) => {
+                    self.pending = Some(Err(err));
+                    return Some(Ok(entry));
+                }
+            }
+        }
+    }
+}